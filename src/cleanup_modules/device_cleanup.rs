@@ -1,24 +1,30 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
 use async_trait::async_trait;
-use error_stack::{IntoReport, Result, ResultExt};
+use error_stack::{bail, IntoReport, Result, ResultExt};
+use log::{info, warn};
 use regex::Regex;
+use serde::de::IgnoredAny;
 use serde::Deserialize;
 use uuid::Uuid;
 use windows::core::HSTRING;
 use windows::Win32::Devices::DeviceAndDriverInstallation::*;
-use windows::Win32::Foundation::BOOL;
+use windows::Win32::Foundation::{GetLastError, BOOL, MAX_PATH, WIN32_ERROR};
 
 use super::*;
 
-use crate::cleanup_modules::create_dump_file;
 use crate::services;
 use crate::services::identifiers;
-use crate::services::regex_cache;
-use crate::services::windows::{enumerate_devices, Device};
+use crate::services::regex_cache::{self, MatchMode};
+use crate::services::windows::{enumerate_all_devices, enumerate_devices, Device};
 use crate::State;
 
 const DEVICE_MODULE_NAME: &str = "Device Cleanup";
 const DEVICE_MODULE_CLI: &str = "device-cleanup";
 const DEVICE_IDENTIFIER: &str = "device_identifiers.json";
+/// `ERROR_NO_SUCH_DEVINST` from SetupAPI, which the windows crate does not export.
+const ERROR_NO_SUCH_DEVINST: WIN32_ERROR = WIN32_ERROR(0xE000020B);
 
 #[derive(Default)]
 pub struct DeviceCleanupModule {
@@ -48,6 +54,14 @@ impl ModuleMetadata for DeviceCleanupModule {
     fn noun(&self) -> &str {
         "devices"
     }
+
+    fn priority(&self) -> u32 {
+        20
+    }
+
+    fn identifier_file(&self) -> &'static str {
+        DEVICE_IDENTIFIER
+    }
 }
 
 #[async_trait]
@@ -59,11 +73,12 @@ impl ModuleStrategy for DeviceCleanupModule {
         let resource = identifiers::get_resource(DEVICE_IDENTIFIER, state)
             .await
             .into_module_report(DEVICE_MODULE_NAME)?;
-        let devices_raw = resource.get_content();
-        let devices: Vec<DeviceToUninstall> = serde_json::from_slice(devices_raw)
-            .into_report()
-            .into_module_report(DEVICE_MODULE_NAME)?;
-        self.objects_to_uninstall = devices;
+        self.objects_to_uninstall = parse_identifiers(
+            DEVICE_MODULE_NAME,
+            DEVICE_IDENTIFIER,
+            resource.get_content(),
+            state,
+        )?;
         Ok(())
     }
 
@@ -75,6 +90,55 @@ impl ModuleStrategy for DeviceCleanupModule {
         self.objects_to_uninstall.as_slice()
     }
 
+    fn exclusion_reason(&self, object: &Self::Object, state: &State) -> Option<String> {
+        let instance_id = object.instance_id().to_lowercase();
+        state
+            .excluded_instances
+            .iter()
+            .find(|excluded| instance_id.contains(&excluded.to_lowercase()))
+            .map(|excluded| {
+                format!(
+                    "its instance id '{}' is excluded by '{}'",
+                    object.instance_id(),
+                    excluded
+                )
+            })
+    }
+
+    /// Removes matched children before their matched parents. Windows may enumerate the
+    /// children of a composite device again if the parent goes first while they remain.
+    fn order_matches(&self, matches: &mut [(Self::Object, usize)]) {
+        sort_children_first(matches, |(device, _)| {
+            (device.instance_id(), device.parent())
+        });
+    }
+
+    fn uninstall_note(&self, object: &Self::Object) -> Option<String> {
+        let note = "The device will be removed with DiUninstallDevice";
+        match object.may_require_reboot() {
+            true => Some(format!(
+                "{}, Windows does not expect it to be removed so a reboot may be required.",
+                note
+            )),
+            false => Some(format!("{}.", note)),
+        }
+    }
+
+    fn object_id(&self, object: &Self::Object) -> String {
+        object.instance_id().to_string()
+    }
+
+    fn describe_object(&self, object: &Self::Object) -> Option<String> {
+        match object.hardware_ids().first().filter(|id| !id.is_empty()) {
+            Some(hardware_id) => Some(format!(
+                "instance id: {}, hardware id: {}",
+                object.instance_id(),
+                hardware_id
+            )),
+            None => Some(format!("instance id: {}", object.instance_id())),
+        }
+    }
+
     async fn uninstall_object(
         &self,
         object: Self::Object,
@@ -101,15 +165,26 @@ impl ModuleStrategy for DeviceCleanupModule {
             )
             .as_bool()
             {
-                let error = windows::core::Error::from_win32();
+                let code = GetLastError();
+                // already removed along with a parent that was uninstalled before it
+                if code == ERROR_NO_SUCH_DEVINST {
+                    bail!(UninstallError::uninstalled(to_uninstall));
+                }
+
+                let error: windows::core::Error = code.into();
                 return Err(error)
                     .into_report()
+                    .attach_printable(services::windows::describe_win32_error(code))
                     .attach_printable_lazy(|| {
                         format!("failed to open device info of {}", object.instance_id())
                     })
                     .into_uninstall_report(to_uninstall);
             }
 
+            if to_uninstall.eject {
+                eject_device(device_info_data.DevInst, object.instance_id());
+            }
+
             let mut reboot: BOOL = false.into();
             if !DiUninstallDevice(
                 None,
@@ -120,9 +195,11 @@ impl ModuleStrategy for DeviceCleanupModule {
             )
             .as_bool()
             {
-                let error = windows::core::Error::from_win32();
+                let code = GetLastError();
+                let error: windows::core::Error = code.into();
                 return Err(error)
                     .into_report()
+                    .attach_printable(services::windows::describe_win32_error(code))
                     .attach_printable_lazy(|| {
                         format!("failed to uninstall device {}", object.instance_id())
                     })
@@ -140,6 +217,13 @@ impl ModuleStrategy for DeviceCleanupModule {
     fn get_dumper(&self) -> Option<&dyn Dumper> {
         Some(&self.device_dumper)
     }
+
+    fn self_test(&self) -> Result<usize, ModuleError> {
+        self_test_identifiers::<Self::Object, Self::ToUninstall>(
+            DEVICE_MODULE_NAME,
+            DEVICE_IDENTIFIER,
+        )
+    }
 }
 
 #[derive(Default)]
@@ -149,31 +233,34 @@ struct DeviceDumper {}
 impl Dumper for DeviceDumper {
     async fn dump(&self, state: &State) -> Result<(), ModuleError> {
         let inf_regex = Regex::new(r"^oem[0-9]+\.inf$").unwrap();
-        let devices: Vec<Device> = enumerate_devices()
+        let devices = match state.ghost_only {
+            true => enumerate_all_devices(),
+            false => enumerate_devices(),
+        };
+        let devices: Vec<Device> = devices
             .into_module_report(DEVICE_MODULE_NAME)?
             .into_iter()
-            .filter(|d| inf_regex.is_match(d.inf_name().unwrap_or("")))
-            .filter(is_of_interest)
+            .filter(|d| !state.ghost_only || !d.is_present())
+            .filter(|d| {
+                state.include_classes.contains(d.class_guid())
+                    || (inf_regex.is_match(d.inf_name().unwrap_or("")) && is_of_interest(d))
+            })
+            .filter(|d| is_of_vendor(d, state.vendor.as_deref()))
             .collect();
 
-        let file_path =
-            get_path_to_dump(state, "devices.json").into_module_report(DEVICE_MODULE_NAME)?;
-        let dump_file = create_dump_file(&file_path).into_module_report(DEVICE_MODULE_NAME)?;
-        let file_name = file_path.as_path().to_str().unwrap();
-
         if devices.is_empty() {
-            println!("No devices to dump");
+            print_dump_progress(state, "No devices to dump");
             return Ok(());
         }
 
-        serde_json::to_writer_pretty(dump_file, &devices)
-            .into_report()
-            .attach_printable_lazy(|| format!("failed to dump devices into '{}'", file_name))
-            .into_module_report(DEVICE_MODULE_NAME)?;
+        let file_path =
+            save_dump(state, "devices", &devices).into_module_report(DEVICE_MODULE_NAME)?;
 
-        match devices.len() {
-            1 => println!("Dumped 1 device to {}", file_name),
-            n => println!("Dumped {} devices to {}", n, file_name),
+        if let Some(file_path) = file_path {
+            match devices.len() {
+                1 => println!("Dumped 1 device to {}", file_path.display()),
+                n => println!("Dumped {} devices to {}", n, file_path.display()),
+            }
         }
 
         Ok(())
@@ -184,24 +271,101 @@ impl Dumper for DeviceDumper {
 #[serde(deny_unknown_fields)]
 pub struct DeviceToUninstall {
     friendly_name: String,
+    #[serde(rename = "meta", default)]
+    _meta: IgnoredAny,
     device_desc: Option<String>,
     manufacturer: Option<String>,
-    hardware_id: Option<String>,
+    inf_provider: Option<String>,
+    hardware_id: Option<Patterns>,
+    /// Compared case-insensitively against the whole hardware id, without regex. Checked in
+    /// addition to `hardware_id`, so when both are set a device must satisfy both.
+    hardware_id_exact: Option<String>,
+    /// Matched against each compatible id, for tablets bound through a generic one such as
+    /// `USB\Class_03`.
+    compatible_id: Option<String>,
+    class_name: Option<String>,
     class_uuid: Option<Uuid>,
+    #[serde(default)]
+    match_mode: MatchMode,
+    #[serde(default)]
+    arch: Arch,
+    /// Safely removes the device before uninstalling it.
+    #[serde(default)]
+    eject: bool,
 }
 
 impl ToUninstall<Device> for DeviceToUninstall {
     fn matches(&self, other: &Device) -> bool {
-        regex_cache::cached_match(other.description(), self.device_desc.as_deref())
-            && regex_cache::cached_match(other.manufacturer(), self.manufacturer.as_deref())
+        let is_match = |input: Option<&str>, pattern: Option<&str>| {
+            regex_cache::cached_match_with(input, pattern, self.match_mode)
+        };
+
+        is_match(other.description(), self.device_desc.as_deref())
+            && is_match(other.manufacturer(), self.manufacturer.as_deref())
+            && is_match(other.inf_provider(), self.inf_provider.as_deref())
+            && is_match(other.class(), self.class_name.as_deref())
             && match self.class_uuid {
                 Some(uuid) => *other.class_guid() == uuid,
                 None => true,
             }
+            && self.arch.includes(services::windows::native_arch())
             && other
                 .hardware_ids()
                 .iter()
-                .any(|hwid| regex_cache::cached_match(Some(hwid), self.hardware_id.as_deref()))
+                .any(|hwid| match &self.hardware_id {
+                    Some(patterns) => patterns
+                        .iter()
+                        .any(|pattern| is_match(Some(hwid), Some(pattern))),
+                    None => true,
+                })
+            && match &self.hardware_id_exact {
+                Some(exact) => other
+                    .hardware_ids()
+                    .iter()
+                    .any(|hwid| hwid.eq_ignore_ascii_case(exact)),
+                None => true,
+            }
+            && match &self.compatible_id {
+                Some(pattern) => other
+                    .compatible_ids()
+                    .iter()
+                    .any(|id| is_match(Some(id), Some(pattern))),
+                None => true,
+            }
+    }
+
+    fn validate(&self) -> CResult<(), ValidationError> {
+        [
+            &self.device_desc,
+            &self.manufacturer,
+            &self.inf_provider,
+            &self.compatible_id,
+            &self.class_name,
+        ]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .chain(self.hardware_id.iter().flat_map(Patterns::iter))
+        .try_for_each(|pattern| regex_cache::validate(pattern, self.match_mode))
+        .map_err(ValidationError::from)
+    }
+}
+
+/// A single pattern, or a list of patterns where any of them may match.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Patterns {
+    One(String),
+    Any(Vec<String>),
+}
+
+impl Patterns {
+    fn iter(&self) -> impl Iterator<Item = &str> {
+        let patterns = match self {
+            Patterns::One(pattern) => std::slice::from_ref(pattern),
+            Patterns::Any(patterns) => patterns.as_slice(),
+        };
+        patterns.iter().map(String::as_str)
     }
 }
 
@@ -211,6 +375,33 @@ impl std::fmt::Display for DeviceToUninstall {
     }
 }
 
+/// Requests the safe removal of a device. Devices that cannot be ejected are still
+/// uninstalled afterwards, so failures are only reported.
+unsafe fn eject_device(dev_inst: u32, instance_id: &str) {
+    let mut veto_type = PNP_VetoTypeUnknown;
+    let mut veto_name = [0u16; MAX_PATH as usize];
+
+    let ret = CM_Request_Device_EjectW(
+        dev_inst,
+        Some(&mut veto_type as *mut _),
+        Some(&mut veto_name[..]),
+        0,
+    );
+    if ret == CR_SUCCESS {
+        info!("Ejected '{}'", instance_id);
+        return;
+    }
+
+    let veto_name = String::from_utf16_lossy(&veto_name);
+    let veto_name = veto_name.trim_end_matches('\0');
+    let message = format!(
+        "Could not eject '{}' ({:?}, vetoed by {:?} '{}'), uninstalling anyway",
+        instance_id, ret, veto_type, veto_name
+    );
+    println!("  {}", message);
+    warn!("{}", message);
+}
+
 fn is_of_interest(device: &Device) -> bool {
     use crate::services::interest::is_of_interest_iter as candidate_iter;
     let strings = [
@@ -224,3 +415,87 @@ fn is_of_interest(device: &Device) -> bool {
 
     candidate_iter(strings)
 }
+
+fn is_of_vendor(device: &Device, vendor: Option<&str>) -> bool {
+    [device.manufacturer(), device.inf_provider()]
+        .into_iter()
+        .any(|s| regex_cache::cached_match(s, vendor))
+}
+
+/// Sorts devices so that each comes before its ancestors among `devices`, given a function
+/// returning the instance id and parent instance id of a device.
+fn sort_children_first<T>(devices: &mut [T], ids: impl Fn(&T) -> (&str, Option<&str>)) {
+    let parents: HashMap<String, Option<String>> = devices
+        .iter()
+        .map(|device| {
+            let (instance_id, parent) = ids(device);
+            (instance_id.to_uppercase(), parent.map(str::to_uppercase))
+        })
+        .collect();
+
+    // number of ancestors among the devices, bounded in case the parents somehow form a cycle
+    let depth = |instance_id: String| {
+        let mut depth = 0;
+        let mut current = parents.get(&instance_id).cloned().flatten();
+        while let Some(parent) = current {
+            match parents.get(&parent) {
+                Some(next) if depth < parents.len() => {
+                    depth += 1;
+                    current = next.clone();
+                }
+                _ => break,
+            }
+        }
+        depth
+    };
+
+    devices.sort_by_cached_key(|device| Reverse(depth(ids(device).0.to_uppercase())));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut devices: Vec<(&str, Option<&str>)>) -> Vec<&str> {
+        sort_children_first(&mut devices, |&(instance_id, parent)| (instance_id, parent));
+        devices
+            .into_iter()
+            .map(|(instance_id, _)| instance_id)
+            .collect()
+    }
+
+    #[test]
+    fn children_are_ordered_before_their_parents() {
+        let devices = vec![
+            (r"USB\VID_1234&PID_5678\1", Some(r"USB\ROOT_HUB30\1")),
+            (
+                r"HID\VID_1234&PID_5678&COL01\2",
+                Some(r"USB\VID_1234&PID_5678\1"),
+            ),
+            (r"USB\VID_ABCD&PID_0001\3", Some(r"USB\ROOT_HUB30\1")),
+            // parents are compared regardless of case
+            (r"SWD\TABLET\4", Some(r"hid\vid_1234&pid_5678&col01\2")),
+        ];
+
+        assert_eq!(
+            sorted(devices),
+            [
+                r"SWD\TABLET\4",
+                r"HID\VID_1234&PID_5678&COL01\2",
+                r"USB\VID_1234&PID_5678\1",
+                r"USB\VID_ABCD&PID_0001\3",
+            ]
+        );
+    }
+
+    #[test]
+    fn parent_cycles_keep_their_order() {
+        let devices = vec![
+            (r"USB\A\1", Some(r"USB\B\2")),
+            (r"USB\B\2", Some(r"USB\A\1")),
+            (r"USB\C\3", None),
+        ];
+
+        assert_eq!(sorted(devices), [r"USB\A\1", r"USB\B\2", r"USB\C\3"]);
+    }
+}
@@ -1,20 +1,25 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use async_trait::async_trait;
+use chrono::NaiveDate;
 use error_stack::{IntoReport, Result, ResultExt};
+use serde::de::IgnoredAny;
 use serde::Deserialize;
 use uuid::Uuid;
-use windows::core::HSTRING;
-use windows::Win32::Devices::DeviceAndDriverInstallation::DiUninstallDriverW;
-use windows::Win32::Foundation::BOOL;
+use windows::Win32::Foundation::{
+    ERROR_BUSY, ERROR_DEVICE_IN_USE, ERROR_SHARING_VIOLATION, WIN32_ERROR,
+};
 
 use super::*;
 
-use crate::cleanup_modules::{create_dump_file, get_path_to_dump};
 use crate::services;
 use crate::services::identifiers;
-use crate::services::regex_cache;
-use crate::services::windows::{enumerate_drivers, Driver};
+use crate::services::regex_cache::{self, MatchMode};
+use crate::services::version::{Version, VersionRange};
+use crate::services::windows::{
+    enumerate_drivers, enumerate_kernel_drivers, uninstall_inf, Driver, KernelDriver,
+};
 use crate::State;
 
 const DRIVER_MODULE_NAME: &str = "Driver Cleanup";
@@ -24,6 +29,7 @@ const DRIVER_IDENTIFIER: &str = "driver_identifiers.json";
 #[derive(Default)]
 pub struct DriverCleanupModule {
     objects_to_uninstall: Vec<DriverToUninstall>,
+    deferred: Mutex<Vec<(PathBuf, String)>>,
     driver_dumper: DriverDumper,
 }
 
@@ -49,6 +55,14 @@ impl ModuleMetadata for DriverCleanupModule {
     fn noun(&self) -> &str {
         "drivers"
     }
+
+    fn priority(&self) -> u32 {
+        30
+    }
+
+    fn identifier_file(&self) -> &'static str {
+        DRIVER_IDENTIFIER
+    }
 }
 
 #[async_trait]
@@ -60,11 +74,12 @@ impl ModuleStrategy for DriverCleanupModule {
         let resource = identifiers::get_resource(DRIVER_IDENTIFIER, state)
             .await
             .into_module_report(DRIVER_MODULE_NAME)?;
-        let drivers_raw = resource.get_content();
-        let drivers: Vec<DriverToUninstall> = serde_json::from_slice(drivers_raw)
-            .into_report()
-            .into_module_report(DRIVER_MODULE_NAME)?;
-        self.objects_to_uninstall = drivers;
+        self.objects_to_uninstall = parse_identifiers(
+            DRIVER_MODULE_NAME,
+            DRIVER_IDENTIFIER,
+            resource.get_content(),
+            state,
+        )?;
         Ok(())
     }
 
@@ -86,36 +101,78 @@ impl ModuleStrategy for DriverCleanupModule {
         let inf_path = Path::new(object.driver_store_location().unwrap())
             .join(object.inf_original_name().unwrap());
 
-        unsafe {
-            let mut reboot: BOOL = false.into();
-            if !DiUninstallDriverW(
-                None,
-                &HSTRING::from(inf_path.as_path()),
-                0,
-                Some(&mut reboot),
-            )
-            .as_bool()
-            {
-                let err = windows::core::Error::from_win32();
-                return Err(err)
-                    .into_report()
-                    .attach_printable_lazy(|| {
-                        format!("failed to uninstall inf: {}", inf_path.display())
-                    })
-                    .into_uninstall_report(to_uninstall);
+        match uninstall_inf(&inf_path) {
+            Ok(reboot) => {
+                if reboot {
+                    run_info.reboot_required = true;
+                }
+
+                Ok(())
             }
+            Err(code) if is_deferrable(code) => {
+                println!("'{}' is in use, will retry later...", to_uninstall);
+                self.deferred
+                    .lock()
+                    .unwrap()
+                    .push((inf_path, to_uninstall.to_string()));
 
-            if reboot.as_bool() {
-                run_info.reboot_required = true;
+                Ok(())
             }
+            Err(code) => inf_uninstall_error(code, &inf_path).into_uninstall_report(to_uninstall),
+        }
+    }
 
-            Ok(())
+    async fn finalize(&self, _state: &State, run_info: &mut ModuleRunInfo) {
+        let deferred = std::mem::take(&mut *self.deferred.lock().unwrap());
+
+        for (inf_path, friendly_name) in deferred {
+            println!("Retrying uninstall of '{}'...", friendly_name);
+
+            match uninstall_inf(&inf_path) {
+                Ok(reboot) => {
+                    if reboot {
+                        run_info.reboot_required = true;
+                    }
+                }
+                Err(code) if is_deferrable(code) => {
+                    println!(
+                        "'{}' is still in use. Reboot and run again to remove it.",
+                        friendly_name
+                    );
+                    run_info.reboot_required = true;
+                }
+                Err(code) => {
+                    let report =
+                        inf_uninstall_error(code, &inf_path).into_uninstall_report(&friendly_name);
+                    eprintln!("{:?}", report.unwrap_err());
+                }
+            }
         }
     }
 
     fn get_dumper(&self) -> Option<&dyn Dumper> {
         Some(&self.driver_dumper)
     }
+
+    fn self_test(&self) -> Result<usize, ModuleError> {
+        self_test_identifiers::<Self::Object, Self::ToUninstall>(
+            DRIVER_MODULE_NAME,
+            DRIVER_IDENTIFIER,
+        )
+    }
+}
+
+fn inf_uninstall_error(code: WIN32_ERROR, inf_path: &Path) -> Result<(), windows::core::Error> {
+    let err: windows::core::Error = code.into();
+    Err(err)
+        .into_report()
+        .attach_printable(services::windows::describe_win32_error(code))
+        .attach_printable_lazy(|| format!("failed to uninstall inf: {}", inf_path.display()))
+}
+
+/// Whether the error is likely to go away once dependent devices or drivers are removed.
+fn is_deferrable(code: WIN32_ERROR) -> bool {
+    [ERROR_BUSY, ERROR_DEVICE_IN_USE, ERROR_SHARING_VIOLATION].contains(&code)
 }
 
 #[derive(Default)]
@@ -127,50 +184,121 @@ impl Dumper for DriverDumper {
         let drivers: Vec<Driver> = enumerate_drivers()
             .into_module_report(DRIVER_MODULE_NAME)?
             .into_iter()
-            .filter(is_of_interest)
+            .filter(|d| state.include_classes.contains(d.class_guid()) || is_of_interest(d))
+            .filter(|d| regex_cache::cached_match(d.provider(), state.vendor.as_deref()))
             .collect();
 
-        let file_path =
-            get_path_to_dump(state, "drivers.json").into_module_report(DRIVER_MODULE_NAME)?;
-        let dump_file = create_dump_file(&file_path).into_module_report(DRIVER_MODULE_NAME)?;
-        let file_name = file_path.as_path().to_str().unwrap();
-
         if drivers.is_empty() {
-            println!("No drivers to dump");
-            return Ok(());
+            print_dump_progress(state, "No drivers to dump");
+            return dump_kernel_drivers(state);
         }
 
-        serde_json::to_writer_pretty(dump_file, &drivers)
-            .into_report()
-            .attach_printable_lazy(|| format!("failed to dump drivers into '{}'", file_name))
-            .into_module_report(DRIVER_MODULE_NAME)?;
+        let file_path =
+            save_dump(state, "drivers", &drivers).into_module_report(DRIVER_MODULE_NAME)?;
 
-        match drivers.len() {
-            1 => println!("Dumped 1 driver into '{}'", file_name),
-            n => println!("Dumped {} drivers into '{}'", n, file_name),
+        if let Some(file_path) = file_path {
+            match drivers.len() {
+                1 => println!("Dumped 1 driver into '{}'", file_path.display()),
+                n => println!("Dumped {} drivers into '{}'", n, file_path.display()),
+            }
         }
 
-        Ok(())
+        dump_kernel_drivers(state)
     }
 }
 
+/// Dumps kernel drivers registered as services, which includes drivers not installed
+/// from an INF and so missing from the driver dump.
+fn dump_kernel_drivers(state: &State) -> Result<(), ModuleError> {
+    use crate::services::interest::is_of_interest_iter as candidate_iter;
+
+    let kernel_drivers: Vec<KernelDriver> = enumerate_kernel_drivers()
+        .into_module_report(DRIVER_MODULE_NAME)?
+        .into_iter()
+        .filter(|d| {
+            candidate_iter(
+                [Some(d.name()), d.display_name(), d.image_path()]
+                    .into_iter()
+                    .flatten(),
+            )
+        })
+        .collect();
+
+    if kernel_drivers.is_empty() {
+        print_dump_progress(state, "No kernel drivers to dump");
+        return Ok(());
+    }
+
+    let file_path = save_dump(state, "kernel-drivers", &kernel_drivers)
+        .into_module_report(DRIVER_MODULE_NAME)?;
+
+    if let Some(file_path) = file_path {
+        match kernel_drivers.len() {
+            1 => println!("Dumped 1 kernel driver into '{}'", file_path.display()),
+            n => println!("Dumped {} kernel drivers into '{}'", n, file_path.display()),
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct DriverToUninstall {
     friendly_name: String,
+    #[serde(rename = "meta", default)]
+    _meta: IgnoredAny,
     original_name: Option<String>,
     provider: Option<String>,
+    driver_version_range: Option<String>,
+    /// Only matches drivers whose `DriverVer` date is before this `yyyy-mm-dd` date.
+    driver_date_before: Option<String>,
     class: Option<Uuid>,
+    #[serde(default)]
+    match_mode: MatchMode,
+    #[serde(default)]
+    arch: Arch,
 }
 
 impl ToUninstall<Driver> for DriverToUninstall {
     fn matches(&self, other: &Driver) -> bool {
-        regex_cache::cached_match(other.inf_original_name(), self.original_name.as_deref())
-            && regex_cache::cached_match(other.provider(), self.provider.as_deref())
+        let is_match = |input: Option<&str>, pattern: &Option<String>| {
+            regex_cache::cached_match_with(input, pattern.as_deref(), self.match_mode)
+        };
+
+        is_match(other.inf_original_name(), &self.original_name)
+            && is_match(other.provider(), &self.provider)
+            && match &self.driver_version_range {
+                Some(range) => is_version_in_range(other.driver_version(), range),
+                None => true,
+            }
+            && match &self.driver_date_before {
+                Some(cutoff) => is_dated_before(other.driver_date(), cutoff),
+                None => true,
+            }
             && match self.class {
                 Some(class) => *other.class_guid() == class,
                 None => true,
             }
+            && self.arch.includes(services::windows::native_arch())
+    }
+
+    fn validate(&self) -> CResult<(), ValidationError> {
+        [&self.original_name, &self.provider]
+            .into_iter()
+            .flatten()
+            .try_for_each(|pattern| regex_cache::validate(pattern, self.match_mode))?;
+
+        if let Some(range) = &self.driver_version_range {
+            VersionRange::parse(range)
+                .ok_or_else(|| ValidationError::value("driver_version_range", range))?;
+        }
+        if let Some(cutoff) = &self.driver_date_before {
+            parse_cutoff(cutoff)
+                .ok_or_else(|| ValidationError::value("driver_date_before", cutoff))?;
+        }
+
+        Ok(())
     }
 }
 
@@ -180,9 +308,78 @@ impl std::fmt::Display for DriverToUninstall {
     }
 }
 
+/// Drivers without a parseable date, or cutoffs that are not `yyyy-mm-dd`, never match.
+fn is_dated_before(date: Option<NaiveDate>, cutoff: &str) -> bool {
+    match (date, parse_cutoff(cutoff)) {
+        (Some(date), Some(cutoff)) => date < cutoff,
+        _ => false,
+    }
+}
+
+fn parse_cutoff(cutoff: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(cutoff.trim(), "%Y-%m-%d").ok()
+}
+
+/// Unparseable versions or ranges never match, so that a typo cannot widen what gets removed.
+fn is_version_in_range(version: Option<&str>, range: &str) -> bool {
+    match (version.and_then(Version::parse), VersionRange::parse(range)) {
+        (Some(version), Some(range)) => range.contains(&version),
+        _ => false,
+    }
+}
+
 fn is_of_interest(driver: &Driver) -> bool {
     use crate::services::interest::is_of_interest_iter as candidate_iter;
 
     let strings = [driver.inf_original_name(), driver.provider()];
     candidate_iter(strings.into_iter().flatten())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_uninstall(identifier: serde_json::Value) -> DriverToUninstall {
+        serde_json::from_value(identifier).unwrap()
+    }
+
+    #[test]
+    fn valid_dates_and_ranges_pass_validation() {
+        let identifier = to_uninstall(serde_json::json!({
+            "friendly_name": "Test",
+            "driver_version_range": ">=1.0, <2.0",
+            "driver_date_before": "2020-01-31",
+        }));
+        assert!(identifier.validate().is_ok());
+    }
+
+    #[test]
+    fn malformed_date_fails_validation() {
+        let identifier = to_uninstall(serde_json::json!({
+            "friendly_name": "Test",
+            "driver_date_before": "31/01/2020",
+        }));
+        assert!(matches!(
+            identifier.validate(),
+            Err(ValidationError::Value {
+                field: "driver_date_before",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn malformed_range_fails_validation() {
+        let identifier = to_uninstall(serde_json::json!({
+            "friendly_name": "Test",
+            "driver_version_range": "newer",
+        }));
+        assert!(matches!(
+            identifier.validate(),
+            Err(ValidationError::Value {
+                field: "driver_version_range",
+                ..
+            })
+        ));
+    }
+}
@@ -5,22 +5,28 @@ use std::path::Path;
 use std::process::{Child, ExitStatus};
 
 use async_trait::async_trait;
-use error_stack::{bail, IntoReport, Result, ResultExt};
+use error_stack::{bail, report, IntoReport, Result, ResultExt};
 use lazy_static::lazy_static;
+use log::{info, warn};
 use regex::Regex;
+use serde::de::IgnoredAny;
 use serde::Deserialize;
 use tokio_util::sync::CancellationToken;
-use winreg::enums::HKEY_LOCAL_MACHINE;
-use winreg::RegKey;
+use uuid::Uuid;
+use windows::Win32::Foundation::MAX_PATH;
 use wmi::{COMLibrary, WMIConnection, WMIError};
 
 use super::*;
 
+use crate::constants;
+use crate::no_color;
 use crate::services;
 use crate::services::identifiers;
-use crate::services::regex_cache;
+use crate::services::regex_cache::{self, MatchMode};
 use crate::services::terminal;
-use crate::services::windows::{enumerate_driver_packages, DriverPackage};
+use crate::services::windows::{
+    enumerate_driver_packages, enumerate_drivers, uninstall_inf, DriverPackage,
+};
 use crate::State;
 
 const MODULE_NAME: &str = "Driver Package Cleanup";
@@ -32,6 +38,8 @@ enum UninstallMethod {
     Normal,
     Deferred,
     RegistryOnly,
+    /// Runs `msiexec /x` with the product code, ignoring the uninstall string.
+    Msi,
 }
 
 #[derive(Default)]
@@ -62,6 +70,14 @@ impl ModuleMetadata for DriverPackageCleanupModule {
     fn noun(&self) -> &str {
         "driver packages"
     }
+
+    fn priority(&self) -> u32 {
+        10
+    }
+
+    fn identifier_file(&self) -> &'static str {
+        IDENTIFIER
+    }
 }
 
 #[async_trait]
@@ -73,12 +89,8 @@ impl ModuleStrategy for DriverPackageCleanupModule {
         let resource = identifiers::get_resource(IDENTIFIER, state)
             .await
             .into_module_report(MODULE_NAME)?;
-        let driver_packages_raw = resource.get_content();
-        let driver_packages: Vec<DriverPackageToUninstall> =
-            serde_json::from_slice(driver_packages_raw)
-                .into_report()
-                .into_module_report(MODULE_NAME)?;
-        self.objects_to_uninstall = driver_packages;
+        self.objects_to_uninstall =
+            parse_identifiers(MODULE_NAME, IDENTIFIER, resource.get_content(), state)?;
         Ok(())
     }
 
@@ -90,15 +102,30 @@ impl ModuleStrategy for DriverPackageCleanupModule {
         self.objects_to_uninstall.as_slice()
     }
 
+    fn object_id(&self, object: &Self::Object) -> String {
+        object.key_name().to_string()
+    }
+
+    fn describe_object(&self, object: &Self::Object) -> Option<String> {
+        match object.system_component() {
+            true => Some("hidden from installed programs".to_string()),
+            false => None,
+        }
+    }
+
     async fn uninstall_object(
         &self,
         object: Self::Object,
         to_uninstall: &Self::ToUninstall,
         state: &State,
-        _run_info: &mut ModuleRunInfo,
+        run_info: &mut ModuleRunInfo,
     ) -> Result<(), UninstallError> {
         use UninstallMethod::*;
 
+        if state.stop_processes {
+            stop_package_processes(&object);
+        }
+
         match &to_uninstall.uninstall_method {
             Normal => run_uninstall_method(uninstall_normal, state, &object, to_uninstall).await,
             Deferred => {
@@ -110,31 +137,103 @@ impl ModuleStrategy for DriverPackageCleanupModule {
                         "failed to open uninstall key for driver package '{}'",
                         to_uninstall.friendly_name
                     )
-                })
+                })?;
+
+                remove_package_infs(&to_uninstall.remove_infs, to_uninstall, run_info)
             }
+            Msi => uninstall_msi(&object, to_uninstall, run_info).await,
+        }
+    }
+
+    fn failure_hint(&self) -> Option<&'static str> {
+        Some("If it keeps failing, try the 'Deferred' or 'RegistryOnly' uninstall method for it.")
+    }
+
+    fn preview_uninstall(&self, object: &Self::Object, to_uninstall: &Self::ToUninstall) {
+        match to_uninstall.uninstall_method {
+            UninstallMethod::RegistryOnly => preview_registry_only(object),
+            UninstallMethod::Msi => match get_product_code(object, to_uninstall) {
+                Some(product_code) => println!(
+                    "Would run 'msiexec.exe {}'",
+                    msi_uninstall_args(&product_code).join(" ")
+                ),
+                None => println!("No MSI product code is known for this package"),
+            },
+            _ => {}
         }
     }
 
     fn get_dumper(&self) -> Option<&dyn Dumper> {
         Some(&self.dumper)
     }
+
+    fn self_test(&self) -> Result<usize, ModuleError> {
+        self_test_identifiers::<Self::Object, Self::ToUninstall>(MODULE_NAME, IDENTIFIER)
+    }
 }
 
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct DriverPackageToUninstall {
     friendly_name: String,
+    #[serde(rename = "meta", default)]
+    _meta: IgnoredAny,
     display_name: Option<String>,
     display_version: Option<String>,
     publisher: Option<String>,
+    install_location: Option<String>,
+    /// Matched against the uninstall key name, e.g. the product code of MSI packages.
+    key_name: Option<String>,
     uninstall_method: UninstallMethod,
+    /// Used by the `Msi` uninstall method. Defaults to the uninstall key name when it is a GUID.
+    product_code: Option<String>,
+    /// Original names of the INFs the package staged in the driver store, removed along with
+    /// its key by the `RegistryOnly` uninstall method.
+    #[serde(default)]
+    remove_infs: Vec<String>,
+    #[serde(default)]
+    match_mode: MatchMode,
+    #[serde(default)]
+    arch: Arch,
+    /// When set, only matches packages that are (or are not) hidden from installed programs.
+    system_component: Option<bool>,
 }
 
 impl ToUninstall<DriverPackage> for DriverPackageToUninstall {
     fn matches(&self, other: &DriverPackage) -> bool {
-        regex_cache::cached_match(other.display_name(), self.display_name.as_deref())
-            && regex_cache::cached_match(other.display_version(), self.display_version.as_deref())
-            && regex_cache::cached_match(other.publisher(), self.publisher.as_deref())
+        let is_match = |input: Option<&str>, pattern: &Option<String>| {
+            regex_cache::cached_match_with(input, pattern.as_deref(), self.match_mode)
+        };
+
+        // packages registered outside of WOW6432Node match the OS architecture
+        let arch = match other.x86() {
+            true => Some("x86"),
+            false => services::windows::native_arch(),
+        };
+
+        is_match(other.display_name(), &self.display_name)
+            && is_match(other.display_version(), &self.display_version)
+            && is_match(other.publisher(), &self.publisher)
+            && is_match(other.install_location(), &self.install_location)
+            && is_match(Some(other.key_name()), &self.key_name)
+            && self.arch.includes(arch)
+            && self
+                .system_component
+                .map_or(true, |hidden| other.system_component() == hidden)
+    }
+
+    fn validate(&self) -> CResult<(), ValidationError> {
+        [
+            &self.display_name,
+            &self.display_version,
+            &self.publisher,
+            &self.install_location,
+            &self.key_name,
+        ]
+        .into_iter()
+        .flatten()
+        .try_for_each(|pattern| regex_cache::validate(pattern, self.match_mode))
+        .map_err(ValidationError::from)
     }
 }
 
@@ -154,28 +253,27 @@ impl Dumper for DriverPackageDumper {
             .into_module_report(MODULE_NAME)?
             .into_iter()
             .filter(is_of_interest)
+            .filter(|p| regex_cache::cached_match(p.publisher(), state.vendor.as_deref()))
+            .filter(|p| is_installed_since(p, state))
             .collect();
 
-        let file_path =
-            get_path_to_dump(state, "driver-packages.json").into_module_report(MODULE_NAME)?;
-        let dump_file = create_dump_file(&file_path).into_module_report(MODULE_NAME)?;
-        let file_name = file_path.as_path().to_str().unwrap();
-
         if driver_packages.is_empty() {
-            println!("No driver packages to dump");
+            print_dump_progress(state, "No driver packages to dump");
             return Ok(());
         }
 
-        serde_json::to_writer_pretty(dump_file, &driver_packages)
-            .into_report()
-            .attach_printable_lazy(|| {
-                format!("failed to dump driver packages into '{}'", file_name)
-            })
+        let file_path = save_dump(state, "driver-packages", &driver_packages)
             .into_module_report(MODULE_NAME)?;
 
-        match driver_packages.len() {
-            1 => println!("Dumped 1 driver package into '{}'", file_name),
-            n => println!("Dumped {} driver packages into '{}'", n, file_name),
+        if let Some(file_path) = file_path {
+            match driver_packages.len() {
+                1 => println!("Dumped 1 driver package into '{}'", file_path.display()),
+                n => println!(
+                    "Dumped {} driver packages into '{}'",
+                    n,
+                    file_path.display()
+                ),
+            }
         }
 
         Ok(())
@@ -188,6 +286,8 @@ impl Dumper for DriverPackageDumper {
 struct ProcessInfo {
     process_id: u32,
     parent_process_id: u32,
+    name: Option<String>,
+    executable_path: Option<String>,
     command_line: Option<String>,
 }
 
@@ -198,15 +298,69 @@ impl ProcessInfo {
     }
 }
 
+/// Terminates processes of interest running from the install location of a driver package.
+fn stop_package_processes(object: &DriverPackage) {
+    use crate::services::interest::is_of_interest_iter as candidate_iter;
+
+    let Some(install_location) = object.install_location().filter(|l| !l.is_empty()) else {
+        return;
+    };
+    let install_location = install_location.to_lowercase();
+
+    let processes = match ProcessInfo::query() {
+        Ok(processes) => processes,
+        Err(err) => {
+            warn!("Failed to query processes: {:?}", err);
+            return;
+        }
+    };
+
+    let current_process_id = std::process::id();
+    let processes = processes.iter().filter(|p| {
+        p.process_id != current_process_id
+            && p.executable_path.as_ref().map_or(false, |path| {
+                path.to_lowercase().starts_with(&install_location)
+            })
+            && candidate_iter(
+                [p.name.as_deref(), p.executable_path.as_deref()]
+                    .into_iter()
+                    .flatten(),
+            )
+    });
+
+    for process in processes {
+        let name = process.name.as_deref().unwrap_or("<unknown>");
+        println!("Stopping '{}' (pid {})...", name, process.process_id);
+
+        match services::windows::terminate_process(process.process_id) {
+            Ok(_) => info!("Stopped '{}' (pid {})", name, process.process_id),
+            Err(err) => no_color(|| warn!("{:?}", err)),
+        }
+    }
+}
+
+/// Whether the package was installed on or after `--since`. Packages without a readable
+/// install date are kept unless `--exclude-undated` is given.
+fn is_installed_since(driver_package: &DriverPackage, state: &State) -> bool {
+    match (state.since, driver_package.install_date()) {
+        (None, _) => true,
+        (Some(since), Some(install_date)) => install_date >= since,
+        (Some(_), None) => !state.exclude_undated,
+    }
+}
+
 fn is_of_interest(driver_package: &DriverPackage) -> bool {
     use crate::services::interest::is_of_interest_iter as candidate_iter;
-    driver_package.display_name().is_some()
-        && driver_package.uninstall_string().is_some()
+    // some packages only register an uninstall string, they are shown by key name instead
+    driver_package.uninstall_string().is_some()
         && candidate_iter(
             [
                 driver_package.display_name(),
                 driver_package.publisher(),
                 driver_package.uninstall_string(),
+                driver_package.install_location(),
+                driver_package.display_version(),
+                Some(driver_package.key_name()),
             ]
             .into_iter()
             .flatten(),
@@ -240,13 +394,17 @@ where
 }
 
 async fn uninstall_normal(
-    _state: &State,
+    state: &State,
     object: &DriverPackage,
     to_uninstall: &DriverPackageToUninstall,
     _ct: CancellationToken,
 ) -> Result<(), UninstallError> {
-    let uninstall_string = object.uninstall_string().unwrap();
-    let child_process = match to_command(uninstall_string).spawn() {
+    // nobody is there to click through the uninstaller without prompts, so prefer the quiet one
+    let uninstall_string = match (state.interactive, object.quiet_uninstall_string()) {
+        (false, Some(quiet_uninstall_string)) => quiet_uninstall_string,
+        _ => object.uninstall_string().unwrap(),
+    };
+    let child_process = match to_command(uninstall_string, object.x86()).spawn() {
         Ok(child) => child,
         Err(err) => match err.kind() {
             ErrorKind::NotFound => bail!(UninstallError::uninstalled(to_uninstall)),
@@ -261,25 +419,98 @@ async fn uninstall_normal(
         },
     };
 
-    wait_for_process_async(child_process)
-        .await
+    let status = match state.interactive {
+        true => wait_for_process_async(child_process).await.map(Some),
+        false => wait_for_unattended_process(child_process).await,
+    };
+    let status = status
         .into_report()
         .attach_printable_lazy(|| {
             format!("failed to wait on child process, exe: {}", uninstall_string)
         })
         .into_uninstall_report(to_uninstall)?;
 
-    Ok(())
+    let Some(status) = status else {
+        return Err(report!(UninstallError::failed(to_uninstall)))
+            .attach_printable("the uninstaller seems to be waiting for input, it was left running")
+            .attach_printable("run without --no-prompt to click through it");
+    };
+
+    check_exit_status(status, object, to_uninstall)
+}
+
+/// Exit code of msiexec when the uninstall succeeded but needs a reboot to complete.
+const ERROR_SUCCESS_REBOOT_REQUIRED: i32 = 3010;
+/// Exit code of msiexec when the product is not installed.
+const ERROR_UNKNOWN_PRODUCT: i32 = 1605;
+
+async fn uninstall_msi(
+    object: &DriverPackage,
+    to_uninstall: &DriverPackageToUninstall,
+    run_info: &mut ModuleRunInfo,
+) -> Result<(), UninstallError> {
+    let Some(product_code) = get_product_code(object, to_uninstall) else {
+        return Err(report!(UninstallError::failed(to_uninstall)))
+            .attach_printable("no MSI product code is known for this package");
+    };
+
+    let args = msi_uninstall_args(&product_code);
+    let child = std::process::Command::new("msiexec.exe")
+        .args(&args)
+        .spawn()
+        .into_report()
+        .attach_printable_lazy(|| format!("failed to launch msiexec {}", args.join(" ")))
+        .into_uninstall_report(to_uninstall)?;
+
+    let status = wait_for_process_async(child)
+        .await
+        .into_report()
+        .attach_printable("failed to wait for msiexec")
+        .into_uninstall_report(to_uninstall)?;
+
+    match status.code() {
+        Some(ERROR_SUCCESS_REBOOT_REQUIRED) => {
+            run_info.reboot_required = true;
+            Ok(())
+        }
+        Some(ERROR_UNKNOWN_PRODUCT) => bail!(UninstallError::uninstalled(to_uninstall)),
+        _ if !status.success() => Err(report!(UninstallError::failed(to_uninstall)))
+            .attach_printable_lazy(|| format!("msiexec exited with {}", status)),
+        _ => Ok(()),
+    }
+}
+
+fn msi_uninstall_args(product_code: &str) -> [String; 4] {
+    [
+        "/x".to_string(),
+        product_code.to_string(),
+        "/qn".to_string(),
+        "/norestart".to_string(),
+    ]
+}
+
+/// Gets the MSI product code of a package, falling back to its uninstall key name as
+/// Windows Installer registers products under their product code.
+fn get_product_code(
+    object: &DriverPackage,
+    to_uninstall: &DriverPackageToUninstall,
+) -> Option<String> {
+    if let Some(product_code) = &to_uninstall.product_code {
+        return Some(product_code.clone());
+    }
+
+    let key_name = Path::new(object.key_name()).file_name()?.to_str()?;
+    Uuid::parse_str(key_name).ok().map(|_| key_name.to_string())
 }
 
 async fn uninstall_deferred(
-    _state: &State,
+    state: &State,
     object: &DriverPackage,
     to_uninstall: &DriverPackageToUninstall,
     _ct: CancellationToken,
 ) -> Result<(), UninstallError> {
     let uninstall_string = object.uninstall_string().unwrap();
-    let mut command = to_command(uninstall_string);
+    let mut command = to_command(uninstall_string, object.x86());
     let target_dir = Path::new(command.get_program())
         .parent()
         .unwrap()
@@ -304,28 +535,30 @@ async fn uninstall_deferred(
 
     let id = child.id();
 
-    tokio::time::sleep(std::time::Duration::from_secs_f32(0.5)).await;
+    let delegate_delay = state
+        .delegate_delay
+        .unwrap_or(constants::DEFAULT_DELEGATE_DELAY);
 
-    let processes = ProcessInfo::query().unwrap();
-    let process_delegate = processes
-        .iter()
-        .filter(|p| p.parent_process_id == id)
-        .find(|p| {
-            p.command_line
-                .as_ref()
-                .map_or(false, |p| p.contains(&target_dir))
-        });
+    let mut process_delegate = None;
+    for _ in 0..constants::DELEGATE_ATTEMPTS {
+        tokio::time::sleep(delegate_delay).await;
+
+        let processes = ProcessInfo::query().unwrap();
+        process_delegate = find_delegate(&processes, id, &target_dir).map(|p| p.process_id);
+        if process_delegate.is_some() {
+            break;
+        }
+    }
 
     if let Some(process_delegate) = process_delegate {
         let ct = CancellationToken::new();
         let results = tokio::join!(
             wait_for_process_async(child),
-            services::windows::wait_for_process_async(
-                process_delegate.process_id,
-                Some(ct.child_token())
-            )
+            services::windows::wait_for_process_async(process_delegate, Some(ct.child_token()))
         );
+        ct.cancel();
         match results {
+            // the exit code of the stub says nothing about the uninstall it handed off
             (Ok(_), Ok(_)) => {}
             (Err(err), _) => {
                 return Err(err)
@@ -339,18 +572,122 @@ async fn uninstall_deferred(
                     .into_uninstall_report(to_uninstall)
             }
         }
-        ct.cancel();
     } else {
-        wait_for_process_async(child)
+        let status = wait_for_process_async(child)
             .await
             .into_report()
             .attach_printable("failed to wait for main uninstaller process")
             .into_uninstall_report(to_uninstall)?;
+        check_exit_status(status, object, to_uninstall)?;
     }
 
     Ok(())
 }
 
+/// Image names of uninstallers known to copy themselves elsewhere, usually `%TEMP%`, and
+/// relaunch from there.
+const DELEGATE_IMAGE_NAMES: &[&str] = &[
+    "au_.exe",      // NSIS
+    "un_a.exe",     // NSIS
+    "_iu14d2n.tmp", // Inno Setup
+    "_isdel.exe",   // InstallShield
+    "isbew64.exe",  // InstallShield
+    "msiexec.exe",  // Windows Installer
+];
+
+/// Finds the process an uninstaller delegated to, among the descendants of `root_id`.
+///
+/// A descendant running from `target_dir` is preferred, otherwise one with a known
+/// uninstaller image name is picked as the real uninstaller may run from a copy.
+fn find_delegate<'a>(
+    processes: &'a [ProcessInfo],
+    root_id: u32,
+    target_dir: &str,
+) -> Option<&'a ProcessInfo> {
+    let descendants: Vec<&ProcessInfo> = processes
+        .iter()
+        .filter(|p| is_descendant_of(processes, p, root_id))
+        .collect();
+
+    let runs_from_target = |p: &&ProcessInfo| {
+        p.command_line
+            .as_ref()
+            .map_or(false, |command_line| command_line.contains(target_dir))
+    };
+    let is_known_delegate = |p: &&ProcessInfo| {
+        p.name.as_ref().map_or(false, |name| {
+            DELEGATE_IMAGE_NAMES
+                .iter()
+                .any(|known| name.eq_ignore_ascii_case(known))
+        })
+    };
+
+    descendants
+        .iter()
+        .copied()
+        .find(runs_from_target)
+        .or_else(|| descendants.iter().copied().find(is_known_delegate))
+}
+
+/// Walks up the parent chain of `process`, stopping at processes that no longer exist.
+fn is_descendant_of(processes: &[ProcessInfo], process: &ProcessInfo, ancestor_id: u32) -> bool {
+    let mut current = process;
+    // process ids are reused, so a cycle is possible
+    for _ in 0..processes.len() {
+        if current.parent_process_id == ancestor_id {
+            return true;
+        }
+        match processes
+            .iter()
+            .find(|p| p.process_id == current.parent_process_id)
+        {
+            Some(parent) if parent.process_id != current.process_id => current = parent,
+            _ => return false,
+        }
+    }
+    false
+}
+
+/// Checks the uninstaller's exit status, treating failures on packages that are
+/// already gone as [`UninstallError::AlreadyUninstalled`]. Exit codes of uninstallers are
+/// not consistent enough to fail on otherwise.
+fn check_exit_status(
+    status: ExitStatus,
+    object: &DriverPackage,
+    to_uninstall: &DriverPackageToUninstall,
+) -> Result<(), UninstallError> {
+    if status.success() {
+        return Ok(());
+    }
+
+    info!("Uninstaller of '{}' exited with {}", object, status);
+    if is_already_uninstalled(object) {
+        bail!(UninstallError::uninstalled(to_uninstall));
+    }
+
+    Ok(())
+}
+
+fn is_already_uninstalled(object: &DriverPackage) -> bool {
+    is_gone(object.install_location(), Path::exists, || {
+        object.is_registered()
+    })
+}
+
+/// Whether a package is gone, given lookups for whether its install location exists and
+/// whether its uninstall key is still registered.
+fn is_gone(
+    install_location: Option<&str>,
+    exists: impl Fn(&Path) -> bool,
+    is_registered: impl Fn() -> bool,
+) -> bool {
+    let install_location_missing = install_location
+        .filter(|location| !location.is_empty())
+        .map_or(false, |location| !exists(Path::new(location)));
+
+    install_location_missing || !is_registered()
+}
+
 async fn wait_for_user(ct: CancellationToken) {
     print!("Complete the uninstall process. If this message is not gone after uninstall is complete, then press any key to continue... ");
     std::io::stdout().flush().unwrap();
@@ -363,7 +700,7 @@ async fn wait_for_process_async(child: Child) -> CResult<ExitStatus, std::io::Er
         loop {
             match child.try_wait() {
                 Ok(Some(exit_code)) => break Ok(exit_code),
-                Ok(None) => tokio::time::sleep(std::time::Duration::from_millis(20)).await,
+                Ok(None) => tokio::time::sleep(constants::POLL_INTERVAL).await,
                 Err(error) => break Err(error),
             }
         }
@@ -372,6 +709,65 @@ async fn wait_for_process_async(child: Child) -> CResult<ExitStatus, std::io::Er
     .unwrap()
 }
 
+/// Waits for an uninstaller launched without prompts. Returns `None` instead of hanging if,
+/// after [`constants::GUI_UNINSTALLER_TIMEOUT`], it shows a window while staying idle for
+/// [`constants::GUI_UNINSTALLER_IDLE_TIME`]. The uninstaller is left running so it can still
+/// be completed by hand.
+async fn wait_for_unattended_process(child: Child) -> CResult<Option<ExitStatus>, std::io::Error> {
+    tokio::spawn(async move {
+        let mut child = child;
+        let started = std::time::Instant::now();
+        let mut activity = IdleTracker::new();
+        loop {
+            match child.try_wait() {
+                Ok(Some(exit_code)) => break Ok(Some(exit_code)),
+                Ok(None)
+                    if started.elapsed() >= constants::GUI_UNINSTALLER_TIMEOUT
+                        && activity.is_idle(child.id())
+                        && services::windows::has_visible_window(child.id()) =>
+                {
+                    warn!(
+                        "Uninstaller (pid {}) seems to be waiting for input",
+                        child.id()
+                    );
+                    break Ok(None);
+                }
+                Ok(None) => tokio::time::sleep(constants::POLL_INTERVAL).await,
+                Err(error) => break Err(error),
+            }
+        }
+    })
+    .await
+    .unwrap()
+}
+
+/// Tracks the CPU time of a process to tell when it stopped doing any work.
+struct IdleTracker {
+    cpu_time: Option<u64>,
+    since: std::time::Instant,
+}
+
+impl IdleTracker {
+    fn new() -> Self {
+        Self {
+            cpu_time: None,
+            since: std::time::Instant::now(),
+        }
+    }
+
+    /// Whether the process used no CPU time for [`constants::GUI_UNINSTALLER_IDLE_TIME`].
+    fn is_idle(&mut self, process_id: u32) -> bool {
+        let cpu_time = services::windows::process_cpu_time(process_id);
+        if cpu_time.is_none() || cpu_time != self.cpu_time {
+            self.cpu_time = cpu_time;
+            self.since = std::time::Instant::now();
+            return false;
+        }
+
+        self.since.elapsed() >= constants::GUI_UNINSTALLER_IDLE_TIME
+    }
+}
+
 fn uninstall_registry_only(
     object: DriverPackage,
     to_uninstall: &DriverPackageToUninstall,
@@ -381,7 +777,8 @@ fn uninstall_registry_only(
     let key_name = key_path.file_name().unwrap();
     let flags = winreg::enums::KEY_WRITE;
 
-    let uninstall_key = RegKey::predef(HKEY_LOCAL_MACHINE)
+    let uninstall_key = object
+        .root_key()
         .open_subkey_with_flags(key_parent, flags)
         .into_report()
         .attach_printable_lazy(|| key_parent.to_string_lossy().to_string())
@@ -394,21 +791,270 @@ fn uninstall_registry_only(
         .into_uninstall_report(to_uninstall)
 }
 
-fn to_command(command: &str) -> std::process::Command {
+/// Prints the key [`uninstall_registry_only`] would delete, along with its current values.
+fn preview_registry_only(object: &DriverPackage) {
+    println!(
+        "Would delete registry key '{}\\{}'",
+        object.scope().root_key_name(),
+        object.key_name()
+    );
+
+    let Ok(key) = object.root_key().open_subkey(object.key_name()) else {
+        return;
+    };
+    for (name, value) in key.enum_values().flatten() {
+        println!("  {} = {}", name, value);
+    }
+}
+
+/// Removes driver store entries whose original INF name is one of `inf_names`.
+fn remove_package_infs(
+    inf_names: &[String],
+    to_uninstall: &DriverPackageToUninstall,
+    run_info: &mut ModuleRunInfo,
+) -> Result<(), UninstallError> {
+    if inf_names.is_empty() {
+        return Ok(());
+    }
+
+    let drivers = enumerate_drivers().into_uninstall_report(to_uninstall)?;
+
+    for driver in drivers {
+        let (Some(location), Some(original_name)) =
+            (driver.driver_store_location(), driver.inf_original_name())
+        else {
+            continue;
+        };
+
+        if !inf_names
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(original_name))
+        {
+            continue;
+        }
+
+        println!("Removing '{}' from the driver store...", driver);
+
+        let inf_path = Path::new(location).join(original_name);
+        match uninstall_inf(&inf_path) {
+            Ok(reboot) => {
+                if reboot {
+                    run_info.reboot_required = true;
+                }
+            }
+            Err(code) => {
+                let err: windows::core::Error = code.into();
+                return Err(err)
+                    .into_report()
+                    .attach_printable(services::windows::describe_win32_error(code))
+                    .attach_printable_lazy(|| {
+                        format!("failed to uninstall inf: {}", inf_path.display())
+                    })
+                    .into_uninstall_report(to_uninstall);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Builds the command to run an uninstall string. `x86` is set for packages registered under
+/// WOW6432Node, whose uninstall strings are written for a 32-bit environment.
+fn to_command(command: &str, x86: bool) -> std::process::Command {
+    let (program, args) =
+        parse_uninstall_string(command, x86, services::windows::expand_environment_strings);
+
+    let mut command = std::process::Command::new(&program);
+    command.args(args);
+
+    // some InstallShield uninstallers look for their setup files in the working directory
+    if let Some(dir) = Path::new(&program).parent().filter(|dir| dir.is_dir()) {
+        command.current_dir(dir);
+    }
+
+    command
+}
+
+/// Splits an uninstall string into the program to launch and its arguments, after expanding
+/// its environment variables with `expand`.
+fn parse_uninstall_string(
+    command: &str,
+    x86: bool,
+    expand: impl Fn(&str) -> String,
+) -> (String, Vec<String>) {
     lazy_static! {
         static ref COMMAND_REGEX: Regex =
             Regex::new(r#""?(?P<command>.*?\.[a-zA-Z]{3})"?(?: (?P<args>.*)?)?"#).unwrap();
+        static ref PROGRAM_FILES_REGEX: Regex =
+            Regex::new(r"(?i)%(?P<var>(?:Common)?ProgramFiles)%").unwrap();
     }
 
-    let captures = COMMAND_REGEX.captures(command).unwrap();
-    let process = captures.name("command").unwrap().as_str();
-    let args = captures.name("args");
+    // a 32-bit process would see the x86 program files in these variables
+    let command = match x86 {
+        true => PROGRAM_FILES_REGEX.replace_all(command, "%${var}(x86)%"),
+        false => command.into(),
+    };
+    let command = expand(&command);
+    let captures = COMMAND_REGEX.captures(&command).unwrap();
+    let program = to_extended_length_path(captures.name("command").unwrap().as_str());
+    let args = captures
+        .name("args")
+        .map(|args| args.as_str().split(' ').map(str::to_string).collect())
+        .unwrap_or_default();
 
-    let mut command = std::process::Command::new(process);
+    (program, args)
+}
 
-    if let Some(args) = args {
-        command.args(args.as_str().split(' '));
+/// Prefixes absolute paths longer than `MAX_PATH` with `\\?\` so they can still be launched.
+fn to_extended_length_path(path: &str) -> String {
+    let is_extendable = path.len() >= MAX_PATH as usize && Path::new(path).is_absolute();
+    if !is_extendable || path.starts_with(r"\\?\") {
+        return path.to_string();
     }
 
-    command
+    let path = path.replace('/', "\\");
+    match path.strip_prefix(r"\\") {
+        Some(unc_path) => format!(r"\\?\UNC\{}", unc_path),
+        None => format!(r"\\?\{}", path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expands the variables used by the tests like a 64-bit system installed on `C:` would.
+    fn expand(input: &str) -> String {
+        input
+            .replace("%SystemRoot%", r"C:\Windows")
+            .replace("%ProgramFiles%", r"C:\Program Files")
+            .replace("%ProgramFiles(x86)%", r"C:\Program Files (x86)")
+    }
+
+    #[test]
+    fn uninstall_string_with_system_root_is_expanded() {
+        let (program, args) =
+            parse_uninstall_string(r"%SystemRoot%\IsUninst.exe -f uninst.isu", false, expand);
+        assert_eq!(program, r"C:\Windows\IsUninst.exe");
+        assert_eq!(args, ["-f", "uninst.isu"]);
+    }
+
+    #[test]
+    fn quoted_uninstall_string_with_system_root_is_expanded() {
+        let (program, args) =
+            parse_uninstall_string(r#""%SystemRoot%\Vendor\uninstall.exe""#, false, expand);
+        assert_eq!(program, r"C:\Windows\Vendor\uninstall.exe");
+        assert!(args.is_empty());
+    }
+
+    #[test]
+    fn x86_uninstall_string_uses_x86_program_files() {
+        let uninstall_string = r"%ProgramFiles%\Vendor\uninstall.exe /S";
+        let (program, args) = parse_uninstall_string(uninstall_string, true, expand);
+        assert_eq!(program, r"C:\Program Files (x86)\Vendor\uninstall.exe");
+        assert_eq!(args, ["/S"]);
+
+        let (program, _) = parse_uninstall_string(uninstall_string, false, expand);
+        assert_eq!(program, r"C:\Program Files\Vendor\uninstall.exe");
+    }
+
+    #[test]
+    fn uninstall_string_is_expanded_from_the_environment() {
+        let system_root = std::env::var("SystemRoot").unwrap();
+        let (program, _) = parse_uninstall_string(
+            r"%SystemRoot%\System32\msiexec.exe",
+            false,
+            services::windows::expand_environment_strings,
+        );
+        assert_eq!(program, format!(r"{}\System32\msiexec.exe", system_root));
+    }
+
+    const UNINSTALLER_ID: u32 = 100;
+    const TARGET_DIR: &str = r"C:\Program Files\Vendor";
+
+    fn process(process_id: u32, parent_process_id: u32, command_line: &str) -> ProcessInfo {
+        ProcessInfo {
+            process_id,
+            parent_process_id,
+            name: command_line.rsplit('\\').next().map(str::to_string),
+            executable_path: None,
+            command_line: Some(command_line.to_string()),
+        }
+    }
+
+    fn delegate_id(processes: &[ProcessInfo]) -> Option<u32> {
+        find_delegate(processes, UNINSTALLER_ID, TARGET_DIR).map(|p| p.process_id)
+    }
+
+    #[test]
+    fn delegate_running_from_target_dir_is_found() {
+        let processes = [
+            process(UNINSTALLER_ID, 1, r"C:\Program Files\Vendor\uninstall.exe"),
+            process(101, UNINSTALLER_ID, r"C:\Program Files\Vendor\remove.exe"),
+        ];
+        assert_eq!(delegate_id(&processes), Some(101));
+    }
+
+    #[test]
+    fn delegate_copied_to_temp_is_found_through_parent_chain() {
+        let processes = [
+            process(UNINSTALLER_ID, 1, r"C:\Program Files\Vendor\uninstall.exe"),
+            process(101, UNINSTALLER_ID, r"C:\Windows\System32\cmd.exe"),
+            process(102, 101, r"C:\Users\User\AppData\Local\Temp\Au_.exe"),
+        ];
+        assert_eq!(delegate_id(&processes), Some(102));
+    }
+
+    #[test]
+    fn delegate_running_from_target_dir_is_preferred() {
+        let processes = [
+            process(UNINSTALLER_ID, 1, r"C:\Program Files\Vendor\uninstall.exe"),
+            process(101, UNINSTALLER_ID, r"C:\Windows\System32\msiexec.exe"),
+            process(102, 101, r"C:\Program Files\Vendor\remove.exe"),
+        ];
+        assert_eq!(delegate_id(&processes), Some(102));
+    }
+
+    #[test]
+    fn unrelated_processes_are_not_delegates() {
+        let processes = [
+            process(UNINSTALLER_ID, 1, r"C:\Program Files\Vendor\uninstall.exe"),
+            process(200, 1, r"C:\Program Files\Vendor\tray.exe"),
+            process(201, 1, r"C:\Windows\System32\msiexec.exe"),
+        ];
+        assert_eq!(delegate_id(&processes), None);
+    }
+
+    #[test]
+    fn parent_cycles_are_not_followed_forever() {
+        let processes = [
+            process(UNINSTALLER_ID, 1, r"C:\Program Files\Vendor\uninstall.exe"),
+            process(200, 201, r"C:\Program Files\Vendor\remove.exe"),
+            process(201, 200, r"C:\Windows\System32\msiexec.exe"),
+        ];
+        assert_eq!(delegate_id(&processes), None);
+    }
+
+    const INSTALL_LOCATION: &str = r"C:\Program Files\Vendor";
+
+    #[test]
+    fn installed_package_is_not_gone() {
+        assert!(!is_gone(Some(INSTALL_LOCATION), |_| true, || true));
+    }
+
+    #[test]
+    fn missing_install_location_is_gone() {
+        assert!(is_gone(Some(INSTALL_LOCATION), |_| false, || true));
+    }
+
+    #[test]
+    fn vanished_key_is_gone() {
+        assert!(is_gone(None, |_| true, || false));
+        assert!(is_gone(Some(INSTALL_LOCATION), |_| true, || false));
+    }
+
+    #[test]
+    fn empty_install_location_is_ignored() {
+        assert!(!is_gone(Some(""), |_| false, || true));
+    }
 }
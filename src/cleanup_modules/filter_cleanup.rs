@@ -0,0 +1,178 @@
+use async_trait::async_trait;
+use error_stack::{IntoReport, Result, ResultExt};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::*;
+
+use crate::services;
+use crate::services::identifiers;
+use crate::services::windows::{enumerate_class_filters, ClassFilter, FilterPosition};
+use crate::State;
+
+const FILTER_MODULE_NAME: &str = "Filter Cleanup";
+const FILTER_MODULE_CLI: &str = "filter-cleanup";
+const FILTER_IDENTIFIER: &str = "filter_identifiers.json";
+
+#[derive(Default)]
+pub struct FilterCleanupModule {
+    objects_to_uninstall: Vec<FilterToUninstall>,
+    filter_dumper: FilterDumper,
+}
+
+impl FilterCleanupModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ModuleMetadata for FilterCleanupModule {
+    fn name(&self) -> &str {
+        FILTER_MODULE_NAME
+    }
+
+    fn cli_name(&self) -> &str {
+        FILTER_MODULE_CLI
+    }
+
+    fn help(&self) -> &str {
+        "remove class filter drivers from the system"
+    }
+
+    fn noun(&self) -> &str {
+        "class filters"
+    }
+
+    fn priority(&self) -> u32 {
+        25
+    }
+
+    fn identifier_file(&self) -> &'static str {
+        FILTER_IDENTIFIER
+    }
+}
+
+#[async_trait]
+impl ModuleStrategy for FilterCleanupModule {
+    type Object = ClassFilter;
+    type ToUninstall = FilterToUninstall;
+
+    async fn initialize(&mut self, state: &State) -> Result<(), ModuleError> {
+        let resource = identifiers::get_resource(FILTER_IDENTIFIER, state)
+            .await
+            .into_module_report(FILTER_MODULE_NAME)?;
+        self.objects_to_uninstall = parse_identifiers(
+            FILTER_MODULE_NAME,
+            FILTER_IDENTIFIER,
+            resource.get_content(),
+            state,
+        )?;
+        Ok(())
+    }
+
+    fn get_objects(&self) -> Result<Vec<Self::Object>, ModuleError> {
+        services::windows::enumerate_class_filters().into_module_report(FILTER_MODULE_NAME)
+    }
+
+    fn get_objects_to_uninstall(&self) -> &[Self::ToUninstall] {
+        self.objects_to_uninstall.as_slice()
+    }
+
+    fn describe_object(&self, object: &Self::Object) -> Option<String> {
+        Some(object.to_string())
+    }
+
+    async fn uninstall_object(
+        &self,
+        object: Self::Object,
+        to_uninstall: &Self::ToUninstall,
+        _state: &State,
+        run_info: &mut ModuleRunInfo,
+    ) -> Result<(), UninstallError> {
+        services::windows::remove_class_filter(&object)
+            .into_report()
+            .attach_printable_lazy(|| format!("failed to remove class filter {}", object))
+            .into_uninstall_report(to_uninstall)?;
+
+        // the class stack keeps the filter loaded until its devices restart
+        run_info.reboot_required = true;
+        Ok(())
+    }
+
+    fn get_dumper(&self) -> Option<&dyn Dumper> {
+        Some(&self.filter_dumper)
+    }
+
+    fn self_test(&self) -> Result<usize, ModuleError> {
+        self_test_identifiers::<Self::Object, Self::ToUninstall>(
+            FILTER_MODULE_NAME,
+            FILTER_IDENTIFIER,
+        )
+    }
+}
+
+/// Class filters are shared by every device of a class, so an identifier must name
+/// the filter and its class exactly rather than through patterns.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FilterToUninstall {
+    friendly_name: String,
+    #[serde(rename = "meta", default)]
+    _meta: IgnoredAny,
+    filter_name: String,
+    class_uuid: Uuid,
+    position: Option<FilterPosition>,
+}
+
+impl ToUninstall<ClassFilter> for FilterToUninstall {
+    fn matches(&self, other: &ClassFilter) -> bool {
+        other.name().eq_ignore_ascii_case(&self.filter_name)
+            && *other.class_guid() == self.class_uuid
+            && match self.position {
+                Some(position) => other.position() == position,
+                None => true,
+            }
+    }
+
+    fn validate(&self) -> CResult<(), ValidationError> {
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for FilterToUninstall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.friendly_name)
+    }
+}
+
+#[derive(Default)]
+struct FilterDumper {}
+
+#[async_trait]
+impl Dumper for FilterDumper {
+    async fn dump(&self, state: &State) -> Result<(), ModuleError> {
+        let filters: Vec<ClassFilter> = enumerate_class_filters()
+            .into_module_report(FILTER_MODULE_NAME)?
+            .into_iter()
+            .filter(|f| services::interest::is_of_interest(Some(f.name())))
+            .collect();
+
+        if filters.is_empty() {
+            print_dump_progress(state, "No class filters to dump");
+            return Ok(());
+        }
+
+        let file_path =
+            save_dump(state, "class-filters", &filters).into_module_report(FILTER_MODULE_NAME)?;
+
+        if let Some(file_path) = file_path {
+            match filters.len() {
+                1 => println!("Dumped 1 class filter into '{}'", file_path.display()),
+                n => println!("Dumped {} class filters into '{}'", n, file_path.display()),
+            }
+        }
+
+        Ok(())
+    }
+}
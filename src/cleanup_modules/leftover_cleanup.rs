@@ -0,0 +1,192 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use error_stack::{IntoReport, Result, ResultExt};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+use super::*;
+
+use crate::services;
+use crate::services::identifiers;
+use crate::services::interest::is_of_interest_iter as candidate_iter;
+use crate::services::regex_cache::{self, MatchMode};
+use crate::services::windows::SystemFile;
+use crate::State;
+
+const LEFTOVER_MODULE_NAME: &str = "Leftover Cleanup";
+const LEFTOVER_MODULE_CLI: &str = "leftover-cleanup";
+const LEFTOVER_IDENTIFIER: &str = "leftover_identifiers.json";
+
+#[derive(Default)]
+pub struct LeftoverCleanupModule {
+    objects_to_uninstall: Vec<LeftoverToUninstall>,
+}
+
+impl LeftoverCleanupModule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ModuleMetadata for LeftoverCleanupModule {
+    fn name(&self) -> &str {
+        LEFTOVER_MODULE_NAME
+    }
+
+    fn cli_name(&self) -> &str {
+        LEFTOVER_MODULE_CLI
+    }
+
+    fn help(&self) -> &str {
+        "remove leftover tablet files such as WinTab DLLs from the system"
+    }
+
+    fn noun(&self) -> &str {
+        "leftover files"
+    }
+
+    fn priority(&self) -> u32 {
+        40
+    }
+
+    fn identifier_file(&self) -> &'static str {
+        LEFTOVER_IDENTIFIER
+    }
+}
+
+#[async_trait]
+impl ModuleStrategy for LeftoverCleanupModule {
+    type Object = SystemFile;
+    type ToUninstall = LeftoverToUninstall;
+
+    async fn initialize(&mut self, state: &State) -> Result<(), ModuleError> {
+        let resource = identifiers::get_resource(LEFTOVER_IDENTIFIER, state)
+            .await
+            .into_module_report(LEFTOVER_MODULE_NAME)?;
+        self.objects_to_uninstall = parse_identifiers(
+            LEFTOVER_MODULE_NAME,
+            LEFTOVER_IDENTIFIER,
+            resource.get_content(),
+            state,
+        )?;
+        Ok(())
+    }
+
+    fn get_objects(&self) -> Result<Vec<Self::Object>, ModuleError> {
+        services::windows::enumerate_system_files(|file_name| {
+            self.objects_to_uninstall
+                .iter()
+                .any(|to_uninstall| to_uninstall.matches_file_name(file_name))
+        })
+        .into_module_report(LEFTOVER_MODULE_NAME)
+    }
+
+    fn get_objects_to_uninstall(&self) -> &[Self::ToUninstall] {
+        self.objects_to_uninstall.as_slice()
+    }
+
+    fn describe_object(&self, object: &Self::Object) -> Option<String> {
+        match (object.company_name(), object.file_version()) {
+            (Some(company_name), Some(version)) => {
+                Some(format!("{}, {} {}", object, company_name, version))
+            }
+            (Some(company_name), None) => Some(format!("{}, {}", object, company_name)),
+            _ => Some(object.to_string()),
+        }
+    }
+
+    /// Files sharing a name with a tablet DLL are only removed when their version info names
+    /// a tablet vendor, so the baseline files shipped by Windows are never touched.
+    fn exclusion_reason(&self, object: &Self::Object, _state: &State) -> Option<String> {
+        let company_name = object.company_name().unwrap_or_default();
+        if company_name.to_lowercase().contains("microsoft") {
+            return Some("it is provided by Microsoft".to_string());
+        }
+
+        let version_info = [object.company_name(), object.product_name()];
+        match candidate_iter(version_info.into_iter().flatten()) {
+            true => None,
+            false => Some("its version info does not name a tablet vendor".to_string()),
+        }
+    }
+
+    async fn uninstall_object(
+        &self,
+        object: Self::Object,
+        to_uninstall: &Self::ToUninstall,
+        _state: &State,
+        run_info: &mut ModuleRunInfo,
+    ) -> Result<(), UninstallError> {
+        let reboot = services::windows::delete_file(Path::new(object.path()))
+            .into_report()
+            .attach_printable_lazy(|| format!("failed to delete '{}'", object))
+            .into_uninstall_report(to_uninstall)?;
+
+        if reboot {
+            println!("'{}' is in use, it will be deleted on reboot.", object);
+            run_info.reboot_required = true;
+        }
+
+        Ok(())
+    }
+
+    fn preview_uninstall(&self, object: &Self::Object, _to_uninstall: &Self::ToUninstall) {
+        println!("  Would delete '{}'", object);
+    }
+
+    fn get_dumper(&self) -> Option<&dyn Dumper> {
+        None
+    }
+
+    fn self_test(&self) -> Result<usize, ModuleError> {
+        self_test_identifiers::<Self::Object, Self::ToUninstall>(
+            LEFTOVER_MODULE_NAME,
+            LEFTOVER_IDENTIFIER,
+        )
+    }
+}
+
+/// A file left behind in `System32` or `SysWOW64` by a tablet driver.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct LeftoverToUninstall {
+    friendly_name: String,
+    #[serde(rename = "meta", default)]
+    _meta: IgnoredAny,
+    file_name: String,
+    company_name: Option<String>,
+    #[serde(default)]
+    match_mode: MatchMode,
+}
+
+impl LeftoverToUninstall {
+    fn matches_file_name(&self, file_name: &str) -> bool {
+        regex_cache::cached_match_with(Some(file_name), Some(&self.file_name), self.match_mode)
+    }
+}
+
+impl ToUninstall<SystemFile> for LeftoverToUninstall {
+    fn matches(&self, other: &SystemFile) -> bool {
+        self.matches_file_name(other.file_name())
+            && regex_cache::cached_match_with(
+                other.company_name(),
+                self.company_name.as_deref(),
+                self.match_mode,
+            )
+    }
+
+    fn validate(&self) -> CResult<(), ValidationError> {
+        [Some(&self.file_name), self.company_name.as_ref()]
+            .into_iter()
+            .flatten()
+            .try_for_each(|pattern| regex_cache::validate(pattern, self.match_mode))
+            .map_err(ValidationError::from)
+    }
+}
+
+impl std::fmt::Display for LeftoverToUninstall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.friendly_name)
+    }
+}
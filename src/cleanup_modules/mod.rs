@@ -1,31 +1,133 @@
 use core::result::Result as CResult;
 use std::{
+    any::Any,
     error::Error,
     fmt::Display,
     fs::File,
+    io::Write,
     path::{Path, PathBuf},
+    sync::Mutex,
 };
 
-use crate::{services::terminal, State};
+use crate::services::identifiers::{self, IdentifierFile};
+use crate::{paint, services::terminal, State};
 use async_trait::async_trait;
-use error_stack::{Context, IntoReport, Report, Result, ResultExt};
+use crossterm::style::Color;
+use error_stack::{report, Context, IntoReport, Report, Result, ResultExt};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+lazy_static! {
+    /// Every dump file written during this run, so only those are packaged by `--zip-dumps`.
+    static ref WRITTEN_DUMPS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+}
+
 mod device_cleanup;
 mod driver_cleanup;
 mod driver_package_cleanup;
+mod filter_cleanup;
+mod leftover_cleanup;
 
 pub use device_cleanup::DeviceCleanupModule;
 pub use driver_cleanup::DriverCleanupModule;
 pub use driver_package_cleanup::DriverPackageCleanupModule;
+pub use filter_cleanup::FilterCleanupModule;
+pub use leftover_cleanup::LeftoverCleanupModule;
 
 #[async_trait]
 pub trait Module {
     fn name(&self) -> &str;
     fn cli_name(&self) -> &str;
     fn help(&self) -> &str;
+    /// Modules with lower priority run first. See [`ModuleMetadata::priority`].
+    fn priority(&self) -> u32;
+    fn identifier_file(&self) -> &'static str;
     async fn run(&mut self, state: &State) -> Result<ModuleRunInfo, ModuleError>;
+    async fn find_matches(&mut self, state: &State) -> Result<Vec<ModuleMatch>, ModuleError>;
+    /// Uninstalls the matches, prompting for each of them when interactive.
+    async fn run_matches(
+        &self,
+        matches: Vec<ModuleMatch>,
+        state: &State,
+    ) -> Result<ModuleRunInfo, ModuleError>;
+    /// Uninstalls the matches without prompting.
+    async fn uninstall_matches(
+        &self,
+        matches: Vec<ModuleMatch>,
+        state: &State,
+    ) -> Result<ModuleRunInfo, ModuleError>;
     fn get_dumper(&self) -> Option<&dyn Dumper>;
+    /// Parses the embedded identifiers and compiles their patterns, returning how many were checked.
+    fn self_test(&self) -> Result<usize, ModuleError>;
+    /// Finds the objects on the system whose id is one of the `uninstalled` object ids.
+    fn find_survivors(&self, uninstalled: &[&str]) -> Result<Vec<String>, ModuleError>;
+}
+
+/// An object found on the system that matched one of the module's identifiers.
+pub struct ModuleMatch {
+    description: String,
+    object: String,
+    details: Option<String>,
+    note: Option<String>,
+    inner: Box<dyn Any + Send + Sync>,
+}
+
+impl ModuleMatch {
+    fn new<T: Any + Send + Sync>(
+        description: String,
+        object: String,
+        details: Option<String>,
+        inner: T,
+    ) -> Self {
+        Self {
+            description,
+            object,
+            details,
+            note: None,
+            inner: Box::new(inner),
+        }
+    }
+
+    fn with_note(mut self, note: Option<String>) -> Self {
+        self.note = note;
+        self
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn details(&self) -> Option<&str> {
+        self.details.as_deref()
+    }
+
+    /// What uninstalling the match involves, shown before asking for confirmation.
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+
+    /// Gets the identifier and a summary of the object it matched.
+    fn to_record(&self) -> (String, String) {
+        let object = match &self.details {
+            // some modules already name the object in its details
+            Some(details) if details.contains(&self.object) => details.clone(),
+            Some(details) => format!("{} ({})", self.object, details),
+            None => self.object.clone(),
+        };
+        (self.description.clone(), object)
+    }
+}
+
+impl Display for ModuleMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.details {
+            Some(details) => write!(f, "{} ({})", self.description, details),
+            None => write!(f, "{}", self.description),
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -60,8 +162,50 @@ impl UninstallError {
     }
 }
 
+/// The Windows architecture an identifier applies to.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Arch {
+    #[default]
+    Any,
+    X86,
+    X64,
+}
+
+impl Arch {
+    /// Whether an object of the given architecture is targeted. Objects of an unknown
+    /// architecture are only targeted by `any`.
+    fn includes(self, arch: Option<&str>) -> bool {
+        match self {
+            Arch::Any => true,
+            Arch::X86 => arch == Some("x86"),
+            Arch::X64 => arch == Some("x64"),
+        }
+    }
+}
+
 trait ToUninstall<T> {
     fn matches(&self, other: &T) -> bool;
+    /// Checks that every pattern and value of this identifier can be used for matching.
+    fn validate(&self) -> CResult<(), ValidationError>;
+}
+
+/// Why an identifier cannot be used for matching.
+#[derive(Debug, Error)]
+enum ValidationError {
+    #[error("pattern does not compile: {0}")]
+    Pattern(#[from] regex::Error),
+    #[error("{field} '{value}' cannot be parsed")]
+    Value { field: &'static str, value: String },
+}
+
+impl ValidationError {
+    fn value(field: &'static str, value: &str) -> Self {
+        Self::Value {
+            field,
+            value: value.to_string(),
+        }
+    }
 }
 
 trait ModuleMetadata {
@@ -69,16 +213,43 @@ trait ModuleMetadata {
     fn cli_name(&self) -> &str;
     fn help(&self) -> &str;
     fn noun(&self) -> &str;
+    /// Determines the default order modules run in, lowest first.
+    ///
+    /// Driver packages go first since their uninstallers remove most of what they installed.
+    /// Devices go before drivers as a driver cannot be removed from the driver store while
+    /// a device still uses it, and class filters go before drivers for the same reason.
+    fn priority(&self) -> u32;
+    /// The identifier file this module loads its identifiers from.
+    fn identifier_file(&self) -> &'static str;
 }
 
 #[async_trait]
 trait ModuleStrategy {
-    type Object: std::fmt::Display + Sync + Send;
+    type Object: std::fmt::Display + Sync + Send + 'static;
     type ToUninstall: ToUninstall<Self::Object> + std::fmt::Display + Sync + Send;
 
     async fn initialize(&mut self, state: &State) -> Result<(), ModuleError>;
     fn get_objects(&self) -> Result<Vec<Self::Object>, ModuleError>;
     fn get_objects_to_uninstall(&self) -> &[Self::ToUninstall];
+    /// Describes the specific object on the system, to tell apart objects matching the same identifier.
+    fn describe_object(&self, _object: &Self::Object) -> Option<String> {
+        None
+    }
+    /// Identifies the object on the system, to find it again after uninstalling it.
+    fn object_id(&self, object: &Self::Object) -> String {
+        object.to_string()
+    }
+    /// Explains how the object will be uninstalled, e.g. when it may need a reboot.
+    fn uninstall_note(&self, _object: &Self::Object) -> Option<String> {
+        None
+    }
+    /// Tells why a matched object must be kept anyway, if it must.
+    fn exclusion_reason(&self, _object: &Self::Object, _state: &State) -> Option<String> {
+        None
+    }
+    /// Reorders the matched objects along with their identifier indices before they are
+    /// uninstalled. Objects are kept in enumeration order by default.
+    fn order_matches(&self, _matches: &mut [(Self::Object, usize)]) {}
     async fn uninstall_object(
         &self,
         object: Self::Object,
@@ -86,7 +257,16 @@ trait ModuleStrategy {
         state: &State,
         run_info: &mut ModuleRunInfo,
     ) -> Result<(), UninstallError>;
+    /// Called in place of [`ModuleStrategy::uninstall_object`] during a dry run, to show what would be changed.
+    fn preview_uninstall(&self, _object: &Self::Object, _to_uninstall: &Self::ToUninstall) {}
+    /// Suggestion shown for objects that failed to uninstall on the last run.
+    fn failure_hint(&self) -> Option<&'static str> {
+        None
+    }
+    /// Called after all matched objects have been processed.
+    async fn finalize(&self, _state: &State, _run_info: &mut ModuleRunInfo) {}
     fn get_dumper(&self) -> Option<&dyn Dumper>;
+    fn self_test(&self) -> Result<usize, ModuleError>;
 }
 
 #[async_trait]
@@ -106,51 +286,132 @@ where
         self.help()
     }
 
+    fn priority(&self) -> u32 {
+        self.priority()
+    }
+
+    fn identifier_file(&self) -> &'static str {
+        ModuleMetadata::identifier_file(self)
+    }
+
     async fn run(&mut self, state: &State) -> Result<ModuleRunInfo, ModuleError> {
-        self.initialize(state).await?;
-        let objects = self.get_objects()?;
-        let objects_to_uninstall = self.get_objects_to_uninstall();
-        let mut module_run_info = ModuleRunInfo::default();
+        let matches = self.find_matches(state).await?;
+        self.run_matches(matches, state).await
+    }
 
-        let mut found = false;
-        for object in objects {
-            let object_to_uninstall = match should_uninstall(&object, objects_to_uninstall) {
-                Some(object_to_uninstall) => object_to_uninstall,
-                None => continue,
-            };
+    async fn run_matches(
+        &self,
+        matches: Vec<ModuleMatch>,
+        state: &State,
+    ) -> Result<ModuleRunInfo, ModuleError> {
+        let mut module_run_info = ModuleRunInfo {
+            matched_count: matches.len(),
+            matches: matches.iter().map(ModuleMatch::to_record).collect(),
+            ..Default::default()
+        };
+
+        if matches.is_empty() {
+            println!("No {} to uninstall is found.", self.noun());
+        } else {
+            let summary = format!("{}: {} matches", self.name(), matches.len());
+            println!("{}", summary);
+            info!("{}", summary);
+        }
 
-            found = true;
-            if state.interactive && !state.dry_run {
-                let prompt =
-                    terminal::prompt_yes_no(&format!("Uninstall '{}'?", object_to_uninstall));
+        let mut approve_all = false;
+        for module_match in matches {
+            if state.interactive && !state.dry_run && !approve_all {
+                let message = match module_match.details() {
+                    Some(details) => {
+                        format!("Uninstall '{}' ({})?", module_match.description(), details)
+                    }
+                    None => format!("Uninstall '{}'?", module_match.description()),
+                };
+                if let Some(note) = module_match.note() {
+                    println!("  {}", note);
+                }
+                let prompt = terminal::prompt_yes_no_all(&message);
 
                 match prompt {
+                    terminal::PromptResult::All => approve_all = true,
                     terminal::PromptResult::No => {
-                        println!("Skipping '{}'...", object_to_uninstall);
+                        let message = format!("Skipping '{}'...", module_match.description());
+                        println!("  {}", paint(message, Color::Yellow));
                         continue;
                     }
                     terminal::PromptResult::Cancel => {
                         println!("Aborting...");
-                        std::process::exit(0);
+                        module_run_info.cancelled = true;
+                        break;
                     }
                     _ => {}
                 }
             }
 
-            println!("Uninstalling '{}'...", object_to_uninstall);
-            if !state.dry_run {
-                let ret = &self
-                    .uninstall_object(object, object_to_uninstall, state, &mut module_run_info)
-                    .await;
+            uninstall_match(self, module_match, state, &mut module_run_info).await;
+        }
+
+        if !state.dry_run && !module_run_info.cancelled {
+            self.finalize(state, &mut module_run_info).await;
+        }
 
-                if let Err(err) = ret {
-                    eprintln!("{:?}", err);
+        Ok(module_run_info)
+    }
+
+    async fn find_matches(&mut self, state: &State) -> Result<Vec<ModuleMatch>, ModuleError> {
+        self.initialize(state).await?;
+        let objects = self.get_objects()?;
+        let objects_to_uninstall = self.get_objects_to_uninstall();
+
+        let mut matched: Vec<(T::Object, usize)> = objects
+            .into_iter()
+            .filter_map(|object| {
+                let index = should_uninstall(&object, objects_to_uninstall)?;
+                let description = objects_to_uninstall[index].to_string();
+                if state.retry_failed && !state.last_run.has_failed(self.name(), &description) {
+                    return None;
                 }
-            }
+                if let Some(reason) = self.exclusion_reason(&object, state) {
+                    let message = format!("Skipping '{}', {}", description, reason);
+                    println!("  {}", paint(message, Color::Yellow));
+                    return None;
+                }
+                Some((object, index))
+            })
+            .collect();
+        self.order_matches(&mut matched);
+
+        let matches = matched
+            .into_iter()
+            .map(|(object, index)| {
+                let description = objects_to_uninstall[index].to_string();
+                let details = self.describe_object(&object);
+                let note = self.uninstall_note(&object);
+                ModuleMatch::new(description, object.to_string(), details, (object, index))
+                    .with_note(note)
+            })
+            .collect();
+
+        Ok(matches)
+    }
+
+    async fn uninstall_matches(
+        &self,
+        matches: Vec<ModuleMatch>,
+        state: &State,
+    ) -> Result<ModuleRunInfo, ModuleError> {
+        let mut module_run_info = ModuleRunInfo {
+            matched_count: matches.len(),
+            matches: matches.iter().map(ModuleMatch::to_record).collect(),
+            ..Default::default()
+        };
+
+        for module_match in matches {
+            uninstall_match(self, module_match, state, &mut module_run_info).await;
         }
 
-        if !found {
-            println!("No {} to uninstall is found.", self.noun());
+        if !state.dry_run {
+            self.finalize(state, &mut module_run_info).await;
         }
 
         Ok(module_run_info)
@@ -159,20 +420,193 @@ where
     fn get_dumper(&self) -> Option<&dyn Dumper> {
         self.get_dumper()
     }
+
+    fn self_test(&self) -> Result<usize, ModuleError> {
+        ModuleStrategy::self_test(self)
+    }
+
+    fn find_survivors(&self, uninstalled: &[&str]) -> Result<Vec<String>, ModuleError> {
+        let survivors = self
+            .get_objects()?
+            .into_iter()
+            .filter(|object| uninstalled.contains(&self.object_id(object).as_str()))
+            .map(|object| object.to_string())
+            .collect();
+
+        Ok(survivors)
+    }
 }
 
-fn should_uninstall<'a, T, U>(object: &T, objects_to_uninstall: &'a [U]) -> Option<&'a U>
+async fn uninstall_match<T>(
+    module: &T,
+    module_match: ModuleMatch,
+    state: &State,
+    run_info: &mut ModuleRunInfo,
+) where
+    T: ModuleMetadata + ModuleStrategy + Sync,
+{
+    let (object, index) = *module_match
+        .inner
+        .downcast::<(T::Object, usize)>()
+        .expect("match should belong to this module");
+    let object_to_uninstall = &module.get_objects_to_uninstall()[index];
+    let object_name = object_to_uninstall.to_string();
+    let object_id = module.object_id(&object);
+
+    if state.last_run.has_failed(module.name(), &object_name) {
+        println!("'{}' failed to uninstall on the last run.", object_name);
+        if let Some(hint) = module.failure_hint() {
+            println!("{}", hint);
+        }
+    }
+
+    let message = format!("Uninstalling '{}'...", object_to_uninstall);
+    println!("  {}", paint(message, Color::Cyan));
+    if !state.dry_run {
+        let ret = module
+            .uninstall_object(object, object_to_uninstall, state, run_info)
+            .await;
+
+        let succeeded = match ret {
+            Err(err) if matches!(err.current_context(), UninstallError::AlreadyUninstalled(_)) => {
+                let message = format!("{}, skipping...", err.current_context());
+                println!("  {}", paint(message, Color::Yellow));
+                true
+            }
+            Err(err) => {
+                let message = format!("Failed to uninstall '{}'", object_to_uninstall);
+                eprintln!("  {}", paint(message, Color::Red));
+                eprintln!("{:?}", err);
+                false
+            }
+            Ok(_) => {
+                let message = format!("Uninstalled '{}'", object_to_uninstall);
+                println!("  {}", paint(message, Color::Green));
+                true
+            }
+        };
+        run_info.outcomes.push((object_name, succeeded));
+        if succeeded {
+            run_info.uninstalled.push(object_id);
+        }
+    } else {
+        module.preview_uninstall(&object, object_to_uninstall);
+    }
+}
+
+fn should_uninstall<T, U>(object: &T, objects_to_uninstall: &[U]) -> Option<usize>
 where
     U: ToUninstall<T>,
 {
     objects_to_uninstall
         .iter()
-        .find(|&object_to_uninstall| object_to_uninstall.matches(object))
+        .position(|object_to_uninstall| object_to_uninstall.matches(object))
+}
+
+/// Parses the identifiers of a module. Unless identifiers are strict, a file that fails to
+/// parse is reported and treated as empty so it does not stop the other modules from running.
+fn parse_identifiers<T: DeserializeOwned>(
+    module_name: &'static str,
+    identifier: &str,
+    content: &[u8],
+    state: &State,
+) -> Result<Vec<T>, ModuleError> {
+    let file = identifiers::parse::<T>(content)
+        .into_report()
+        .attach_printable_lazy(|| format!("cannot parse '{}'", identifier))
+        .into_module_report(module_name);
+
+    let digest = identifiers::digest(content);
+
+    match file {
+        Ok(file) if file.metadata.enabled == Some(false) => {
+            if state.force_enabled.iter().any(|name| name == module_name) {
+                info!(
+                    "'{}' is disabled by '{}', running it anyway",
+                    module_name, identifier
+                );
+                return Ok(file.identifiers);
+            }
+
+            println!(
+                "'{}' is disabled by its identifiers, skipping.",
+                module_name
+            );
+            info!(
+                "'{}' is disabled by '{}' (sha256: {})",
+                module_name, identifier, digest
+            );
+            Ok(Vec::new())
+        }
+        Ok(file) => {
+            println!(
+                "Loaded '{}' {} (sha256: {})",
+                identifier,
+                file.metadata,
+                &digest[..12]
+            );
+            info!(
+                "Loaded '{}' {} (sha256: {})",
+                identifier, file.metadata, digest
+            );
+            Ok(file.identifiers)
+        }
+        Err(err) if !state.strict_identifiers => {
+            eprintln!("{:?}", err);
+            println!("Continuing without the identifiers of '{}'.", module_name);
+            crate::no_color(|| warn!("{:?}", err));
+            Ok(Vec::new())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+fn self_test_identifiers<T, U>(
+    module_name: &'static str,
+    identifier: &'static str,
+) -> Result<usize, ModuleError>
+where
+    U: ToUninstall<T> + DeserializeOwned + Display,
+{
+    let resource = identifiers::get_resource_embed(identifier).into_module_report(module_name)?;
+    let file: IdentifierFile<U> = identifiers::parse(resource.get_content())
+        .into_report()
+        .attach_printable_lazy(|| format!("cannot parse '{}'", identifier))
+        .into_module_report(module_name)?;
+
+    let failures: Vec<String> = file
+        .identifiers
+        .iter()
+        .filter_map(|to_uninstall| {
+            let err = to_uninstall.validate().err()?;
+            Some(format!("invalid identifier '{}': {}", to_uninstall, err))
+        })
+        .collect();
+
+    if !failures.is_empty() {
+        let report = failures.into_iter().fold(
+            report!(ModuleError { name: module_name }),
+            |report, failure| report.attach_printable(failure),
+        );
+        return Err(report);
+    }
+
+    Ok(file.identifiers.len())
 }
 
 #[derive(Default)]
 pub struct ModuleRunInfo {
     pub reboot_required: bool,
+    /// Number of objects on the system that matched an identifier.
+    pub matched_count: usize,
+    /// Every object an uninstall was attempted on, and whether it succeeded.
+    pub outcomes: Vec<(String, bool)>,
+    /// The id of every object that was uninstalled, or found to be already uninstalled.
+    pub uninstalled: Vec<String>,
+    /// Every identifier that matched an object, with a summary of the object.
+    pub matches: Vec<(String, String)>,
+    /// Whether the user cancelled the run while this module was running.
+    pub cancelled: bool,
 }
 
 #[async_trait]
@@ -180,23 +614,128 @@ pub trait Dumper {
     async fn dump(&self, state: &State) -> Result<(), ModuleError>;
 }
 
-fn get_path_to_dump(state: &State, filename: &str) -> Result<PathBuf, std::io::Error> {
-    let dump_path = Path::join(&state.current_path, "dumps");
+/// How dumpers write the objects they found.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// A pretty-printed JSON array.
+    #[default]
+    Json,
+    /// One JSON object per line.
+    Jsonl,
+}
+
+impl DumpFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            DumpFormat::Json => "json",
+            DumpFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+/// Writes the objects into the dump in the format chosen with `--dump-format`.
+pub(crate) fn write_dump<T: Serialize>(
+    state: &State,
+    mut writer: impl Write,
+    objects: &[T],
+) -> CResult<(), serde_json::Error> {
+    match state.dump_format {
+        DumpFormat::Json => serde_json::to_writer_pretty(writer, objects),
+        DumpFormat::Jsonl => objects.iter().try_for_each(|object| {
+            serde_json::to_writer(&mut writer, object)?;
+            writer.write_all(b"\n").map_err(serde_json::Error::io)
+        }),
+    }
+}
+
+/// Dumps the objects under `name`, see [`emit_dump`].
+pub(crate) fn save_dump<T: Serialize>(
+    state: &State,
+    name: &str,
+    objects: &[T],
+) -> Result<Option<PathBuf>, std::io::Error> {
+    emit_dump(state, &dump_file_name(state, name), |writer| {
+        write_dump(state, writer, objects)
+    })
+}
+
+/// Writes a dump into `file_name` in the dump directory, or to stdout between delimiters
+/// with `--dump-stdout`. Returns the path of the dump file, or `None` if written to stdout.
+pub(crate) fn emit_dump(
+    state: &State,
+    file_name: &str,
+    write: impl FnOnce(&mut dyn Write) -> CResult<(), serde_json::Error>,
+) -> Result<Option<PathBuf>, std::io::Error> {
+    if state.dump_stdout {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "----- BEGIN {} -----", file_name)
+            .and_then(|_| write(&mut stdout).map_err(std::io::Error::from))
+            .and_then(|_| writeln!(stdout, "\n----- END {} -----", file_name))
+            .into_report()
+            .attach_printable_lazy(|| format!("failed to write '{}' to stdout", file_name))?;
+        return Ok(None);
+    }
+
+    let file_path = get_path_to_dump(state, file_name)?;
+    let mut file = create_dump_file(&file_path)?;
+    write(&mut file)
+        .map_err(std::io::Error::from)
+        .into_report()
+        .attach_printable_lazy(|| format!("failed to write '{}'", file_path.display()))?;
+
+    WRITTEN_DUMPS.lock().unwrap().push(file_path.clone());
+    Ok(Some(file_path))
+}
+
+/// The dump files written so far during this run.
+pub(crate) fn written_dumps() -> Vec<PathBuf> {
+    WRITTEN_DUMPS.lock().unwrap().clone()
+}
+
+/// Prints the progress of a dump, unless the dumps themselves are written to stdout.
+pub(crate) fn print_dump_progress(state: &State, message: &str) {
+    if !state.dump_stdout {
+        println!("{}", message);
+    }
+}
+
+/// The file name of a dump, with the extension of the chosen dump format.
+pub(crate) fn dump_file_name(state: &State, name: &str) -> String {
+    format!("{}.{}", name, state.dump_format.extension())
+}
+
+fn get_dump_dir(state: &State) -> PathBuf {
+    match &state.dump_path {
+        Some(dump_path) => dump_path.clone(),
+        None => Path::join(&state.current_path, "dumps"),
+    }
+}
+
+/// Creates the dump directory if it does not exist yet, returning its path.
+pub(crate) fn create_dump_dir(state: &State) -> Result<PathBuf, std::io::Error> {
+    let dump_path = get_dump_dir(state);
     if !dump_path.exists() {
         std::fs::create_dir_all(&dump_path)
             .into_report()
-            .attach_printable_lazy(|| format!("cannot create path '{}'", dump_path.display()))?;
+            .attach_printable_lazy(|| format!("cannot create path '{}'", dump_path.display()))
+            .attach_printable("a writable directory can be chosen with --dump-path")?;
     }
 
+    Ok(dump_path)
+}
+
+pub(crate) fn get_path_to_dump(state: &State, filename: &str) -> Result<PathBuf, std::io::Error> {
+    let dump_path = create_dump_dir(state)?;
     let file_path = Path::join(&dump_path, filename);
 
     Ok(file_path)
 }
 
-fn create_dump_file(path: &Path) -> Result<File, std::io::Error> {
+pub(crate) fn create_dump_file(path: &Path) -> Result<File, std::io::Error> {
     let file = File::create(path)
         .into_report()
-        .attach_printable_lazy(|| format!("cannot create file '{}'", path.display()))?;
+        .attach_printable_lazy(|| format!("cannot create file '{}'", path.display()))
+        .attach_printable("a writable directory can be chosen with --dump-path")?;
 
     Ok(file)
 }
@@ -1,32 +1,146 @@
 pub mod cleanup_modules;
 pub(crate) mod services;
 
-use std::path::PathBuf;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
+use chrono::NaiveDate;
+use clap::parser::ValueSource;
 use clap::ArgMatches;
-use cleanup_modules::Module;
+use cleanup_modules::{DumpFormat, Module, ModuleError, ModuleMatch, ModuleRunInfo};
 use crossterm::event::KeyCode;
-use crossterm::style::Stylize;
+use crossterm::style::{Color, Stylize};
 use error_stack::fmt::ColorMode;
-use error_stack::Report;
+use error_stack::{IntoReport, Report, ResultExt};
+use log::{info, warn};
+use serde::Serialize;
+use services::correlation::{self, CorrelationError};
+use services::last_run::{LastRun, Outcome};
+use uuid::Uuid;
 
-use crate::services::terminal::{read_key_async, WaitResult};
+use crate::services::terminal::{self, read_key_async, WaitResult};
 
 pub mod constants {
     pub const CLI_NAME: &str = "TabletDriverCleanup";
+    /// Exit code used with `--nothing-found-code` when no module found anything to uninstall.
+    pub const EXIT_NOTHING_FOUND: i32 = 2;
+    /// Environment variable that skips the administrator check for testing. Runs that are not
+    /// actually elevated are turned into dry runs.
+    pub const FORCE_ELEVATED_ENV: &str = "TDC_FORCE_ELEVATED";
     pub const DRY_RUN: &str = "dry_run";
     pub const DUMP: &str = "dump";
     pub const INTERACTIVE: &str = "interactive";
     pub const USE_CACHE: &str = "use_cache";
     pub const ALLOW_UPDATES: &str = "allow_updates";
+    pub const BATCH_CONFIRM: &str = "batch_confirm";
+    pub const ELEVATE: &str = "elevate";
+    pub const LIST_MODULES: &str = "list_modules";
+    pub const LIST_MATCHES: &str = "list_matches";
+    pub const ALLOW_REBOOT: &str = "allow_reboot";
+    pub const VENDOR: &str = "vendor";
+    pub const MODULE_ORDER: &str = "module_order";
+    pub const STOP_PROCESSES: &str = "stop_processes";
+    pub const DUMP_PATH: &str = "dump_path";
+    pub const GHOST_ONLY: &str = "ghost_only";
+    pub const PROXY: &str = "proxy";
+    pub const VERSION_CHECK: &str = "version_check";
+    pub const ZIP_DUMPS: &str = "zip_dumps";
+    pub const REBOOT_TIMEOUT: &str = "reboot_timeout";
+    pub const NO_COLOR: &str = "no_color";
+    pub const PREVIEW: &str = "preview";
+    pub const LAST_RUN: &str = "last_run";
+    pub const INCLUDE_CLASS: &str = "include_class";
+    pub const STRICT_IDENTIFIERS: &str = "strict_identifiers";
+    pub const CORRELATE: &str = "correlate";
+    pub const RETRY_FAILED: &str = "retry_failed";
+    pub const DELEGATE_DELAY: &str = "delegate_delay";
+    pub const EXCLUDE_INSTANCE: &str = "exclude_instance";
+    pub const FAIL_FAST: &str = "fail_fast";
+    pub const KEEP_CACHE_DAYS: &str = "keep_cache_days";
+    pub const TRACE_FFI: &str = "trace_ffi";
+    pub const MODULES_FROM: &str = "modules_from";
+    pub const CLEAN_ORPHAN_STORE: &str = "clean_orphan_store";
+    pub const DUMP_FORMAT: &str = "dump_format";
+    pub const VERIFY: &str = "verify";
+    pub const SINCE: &str = "since";
+    pub const EXCLUDE_UNDATED: &str = "exclude_undated";
+    pub const ENABLE: &str = "enable";
+    pub const CLEAN: &str = "clean";
+    pub const INSPECT_DEVICE: &str = "inspect_device";
+    pub const DUMP_STDOUT: &str = "dump_stdout";
+    pub const REFRESH: &str = "refresh";
+    pub const PRE_MODULE_HOOK: &str = "pre_module_hook";
+    pub const POST_MODULE_HOOK: &str = "post_module_hook";
+    pub const EXPORT_MATCHES: &str = "export_matches";
+    pub const NOTHING_FOUND_CODE: &str = "nothing_found_code";
+    pub const TEST_INTEREST: &str = "test_interest";
+    pub const SELF_TEST: &str = "self_test";
+
+    use std::time::Duration;
+
+    /// How often key presses and launched uninstallers are polled. Shorter intervals react
+    /// sooner at the cost of more wakeups.
+    pub const POLL_INTERVAL: Duration = Duration::from_millis(20);
+    /// How long to block on a process handle at a time. Longer waits make cancellation
+    /// less responsive.
+    pub const PROCESS_WAIT_INTERVAL: Duration = Duration::from_millis(10);
+    /// How long to wait after launching an uninstaller before looking for the process it
+    /// delegated to. Too short misses delegates that are slow to start, too long makes every
+    /// uninstall slower. Can be overridden with `--delegate-delay`.
+    pub const DEFAULT_DELEGATE_DELAY: Duration = Duration::from_millis(500);
+    /// How many times to look for the delegated process before waiting on the launched
+    /// uninstaller alone.
+    pub const DELEGATE_ATTEMPTS: u32 = 4;
+    /// How long an uninstaller launched without prompts runs before it is checked for
+    /// waiting on clicks that will never come.
+    pub const GUI_UNINSTALLER_TIMEOUT: Duration = Duration::from_secs(30);
+    /// How long an uninstaller showing a window must use no CPU time to be considered waiting
+    /// for input. Slow uninstallers showing progress still do some work in this time.
+    pub const GUI_UNINSTALLER_IDLE_TIME: Duration = Duration::from_secs(10);
 }
 
 pub type ModuleCollection = Vec<Box<dyn Module>>;
 
+/// Why a run did not complete.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("This program must be run as administrator")]
+    NotElevated,
+    #[error("Errors were encountered while running '{0}'")]
+    ModuleFailed(String),
+    #[error("Cancelled by the user")]
+    Cancelled,
+    #[error("Invalid module list: {0}")]
+    InvalidModuleList(String),
+    #[error("Failed to dump {0}")]
+    DumpFailed(String),
+    #[error("Nothing could be dumped")]
+    NothingDumped,
+    #[error("{0}")]
+    InvalidInterest(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Cancelled => 0,
+            Error::NotElevated
+            | Error::ModuleFailed(_)
+            | Error::InvalidModuleList(_)
+            | Error::DumpFailed(_)
+            | Error::NothingDumped
+            | Error::InvalidInterest(_) => 1,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Mode {
     Run,
     Dump,
+    ListMatches,
 }
 
 #[derive(Default)]
@@ -42,6 +156,52 @@ pub struct State {
     pub dry_run: bool,
     pub use_cache: bool,
     pub allow_updates: bool,
+    pub batch_confirm: bool,
+    pub elevate: bool,
+    pub allow_reboot: bool,
+    pub vendor: Option<String>,
+    pub module_order: Vec<String>,
+    pub stop_processes: bool,
+    pub dump_path: Option<PathBuf>,
+    pub ghost_only: bool,
+    pub proxy: Option<String>,
+    pub version_check: bool,
+    pub zip_dumps: bool,
+    pub reboot_timeout: Option<u64>,
+    pub no_color: bool,
+    pub preview: bool,
+    pub record_last_run: bool,
+    pub last_run: LastRun,
+    pub include_classes: Vec<Uuid>,
+    pub strict_identifiers: bool,
+    pub correlate: bool,
+    pub retry_failed: bool,
+    pub delegate_delay: Option<Duration>,
+    pub excluded_instances: Vec<String>,
+    pub continue_on_error: bool,
+    pub keep_cache_days: Option<u64>,
+    pub trace_ffi: bool,
+    pub clean_orphan_store: bool,
+    pub dump_format: DumpFormat,
+    pub verify: bool,
+    pub since: Option<NaiveDate>,
+    pub exclude_undated: bool,
+    /// Names of the modules to run even when their identifiers disable them.
+    pub force_enabled: Vec<String>,
+    /// Whether to let the user pick the modules to run before starting.
+    pub pick_modules: bool,
+    /// Whether dumps are written to stdout instead of files.
+    pub dump_stdout: bool,
+    /// Commands run before and after each module, with the module cli name as an argument.
+    pub pre_module_hook: Option<String>,
+    pub post_module_hook: Option<String>,
+    /// Where to write the identifiers that matched during the run.
+    pub export_matches: Option<PathBuf>,
+    /// Identifier files to fetch online even when they are cached.
+    pub refresh_identifiers: Vec<String>,
+    /// Whether to run without administrator rights, for testing. See
+    /// [`constants::FORCE_ELEVATED_ENV`].
+    pub force_elevated: bool,
 }
 
 #[derive(Default)]
@@ -79,6 +239,191 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn batch_confirm(mut self, batch_confirm: bool) -> Self {
+        self.config.state.batch_confirm = batch_confirm;
+        self
+    }
+
+    pub fn elevate(mut self, elevate: bool) -> Self {
+        self.config.state.elevate = elevate;
+        self
+    }
+
+    pub fn allow_reboot(mut self, allow_reboot: bool) -> Self {
+        self.config.state.allow_reboot = allow_reboot;
+        self
+    }
+
+    pub fn vendor(mut self, vendor: Option<String>) -> Self {
+        self.config.state.vendor = vendor;
+        self
+    }
+
+    pub fn module_order(mut self, module_order: Vec<String>) -> Self {
+        self.config.state.module_order = module_order;
+        self
+    }
+
+    pub fn stop_processes(mut self, stop_processes: bool) -> Self {
+        self.config.state.stop_processes = stop_processes;
+        self
+    }
+
+    pub fn dump_path(mut self, dump_path: Option<PathBuf>) -> Self {
+        self.config.state.dump_path = dump_path;
+        self
+    }
+
+    pub fn ghost_only(mut self, ghost_only: bool) -> Self {
+        self.config.state.ghost_only = ghost_only;
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Option<String>) -> Self {
+        self.config.state.proxy = proxy;
+        self
+    }
+
+    pub fn version_check(mut self, version_check: bool) -> Self {
+        self.config.state.version_check = version_check;
+        self
+    }
+
+    pub fn zip_dumps(mut self, zip_dumps: bool) -> Self {
+        self.config.state.zip_dumps = zip_dumps;
+        self
+    }
+
+    pub fn reboot_timeout(mut self, reboot_timeout: Option<u64>) -> Self {
+        self.config.state.reboot_timeout = reboot_timeout;
+        self
+    }
+
+    pub fn no_color(mut self, no_color: bool) -> Self {
+        self.config.state.no_color = no_color;
+        self
+    }
+
+    pub fn preview(mut self, preview: bool) -> Self {
+        self.config.state.preview = preview;
+        self
+    }
+
+    pub fn record_last_run(mut self, record_last_run: bool) -> Self {
+        self.config.state.record_last_run = record_last_run;
+        self
+    }
+
+    pub fn include_classes(mut self, include_classes: Vec<Uuid>) -> Self {
+        self.config.state.include_classes = include_classes;
+        self
+    }
+
+    pub fn strict_identifiers(mut self, strict_identifiers: bool) -> Self {
+        self.config.state.strict_identifiers = strict_identifiers;
+        self
+    }
+
+    pub fn correlate(mut self, correlate: bool) -> Self {
+        self.config.state.correlate = correlate;
+        self
+    }
+
+    pub fn retry_failed(mut self, retry_failed: bool) -> Self {
+        self.config.state.retry_failed = retry_failed;
+        self
+    }
+
+    pub fn delegate_delay(mut self, delegate_delay: Option<Duration>) -> Self {
+        self.config.state.delegate_delay = delegate_delay;
+        self
+    }
+
+    pub fn excluded_instances(mut self, excluded_instances: Vec<String>) -> Self {
+        self.config.state.excluded_instances = excluded_instances;
+        self
+    }
+
+    pub fn continue_on_error(mut self, continue_on_error: bool) -> Self {
+        self.config.state.continue_on_error = continue_on_error;
+        self
+    }
+
+    pub fn keep_cache_days(mut self, keep_cache_days: Option<u64>) -> Self {
+        self.config.state.keep_cache_days = keep_cache_days;
+        self
+    }
+
+    pub fn trace_ffi(mut self, trace_ffi: bool) -> Self {
+        self.config.state.trace_ffi = trace_ffi;
+        self
+    }
+
+    pub fn clean_orphan_store(mut self, clean_orphan_store: bool) -> Self {
+        self.config.state.clean_orphan_store = clean_orphan_store;
+        self
+    }
+
+    pub fn dump_format(mut self, dump_format: DumpFormat) -> Self {
+        self.config.state.dump_format = dump_format;
+        self
+    }
+
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.config.state.verify = verify;
+        self
+    }
+
+    pub fn since(mut self, since: Option<NaiveDate>) -> Self {
+        self.config.state.since = since;
+        self
+    }
+
+    pub fn exclude_undated(mut self, exclude_undated: bool) -> Self {
+        self.config.state.exclude_undated = exclude_undated;
+        self
+    }
+
+    pub fn force_enabled(mut self, force_enabled: Vec<String>) -> Self {
+        self.config.state.force_enabled = force_enabled;
+        self
+    }
+
+    pub fn refresh_identifiers(mut self, refresh_identifiers: Vec<String>) -> Self {
+        self.config.state.refresh_identifiers = refresh_identifiers;
+        self
+    }
+
+    pub fn force_elevated(mut self, force_elevated: bool) -> Self {
+        self.config.state.force_elevated = force_elevated;
+        self
+    }
+
+    pub fn pre_module_hook(mut self, pre_module_hook: Option<String>) -> Self {
+        self.config.state.pre_module_hook = pre_module_hook;
+        self
+    }
+
+    pub fn post_module_hook(mut self, post_module_hook: Option<String>) -> Self {
+        self.config.state.post_module_hook = post_module_hook;
+        self
+    }
+
+    pub fn export_matches(mut self, export_matches: Option<PathBuf>) -> Self {
+        self.config.state.export_matches = export_matches;
+        self
+    }
+
+    pub fn dump_stdout(mut self, dump_stdout: bool) -> Self {
+        self.config.state.dump_stdout = dump_stdout;
+        self
+    }
+
+    pub fn pick_modules(mut self, pick_modules: bool) -> Self {
+        self.config.state.pick_modules = pick_modules;
+        self
+    }
+
     pub fn add_module(mut self, module: Box<dyn Module>) -> Self {
         self.config.modules.push(module);
         self
@@ -92,63 +437,225 @@ impl ConfigBuilder {
 #[derive(Default)]
 struct RunState {
     pub need_reboot: bool,
+    pub matched_count: usize,
+    pub outcomes: Vec<Outcome>,
+    /// The module and id of every object that was uninstalled.
+    pub uninstalled: Vec<(String, String)>,
+    pub matches: Vec<MatchedIdentifier>,
+    pub failed_modules: Vec<String>,
 }
 
-pub async fn run(config: Config) {
+/// An identifier that matched an object during a run, as written by `--export-matches`.
+#[derive(Serialize)]
+struct MatchedIdentifier {
+    module: String,
+    identifier: String,
+    object: String,
+}
+
+impl RunState {
+    /// Records a failed module when continuing past errors, passing any other error on.
+    fn tolerate<T: Default>(
+        &mut self,
+        state: &State,
+        result: Result<T, Error>,
+    ) -> Result<T, Error> {
+        match result {
+            Err(Error::ModuleFailed(name)) if state.continue_on_error => {
+                self.failed_modules.push(name);
+                Ok(T::default())
+            }
+            result => result,
+        }
+    }
+
+    /// Adds the outcome of a module, failing if the user cancelled during it.
+    fn add(&mut self, module_name: &str, module_run: ModuleRunInfo) -> Result<(), Error> {
+        if module_run.reboot_required {
+            self.need_reboot = true;
+        }
+        self.matched_count += module_run.matched_count;
+        self.outcomes.extend(
+            module_run
+                .outcomes
+                .into_iter()
+                .map(|(object, succeeded)| Outcome {
+                    module: module_name.to_string(),
+                    object,
+                    succeeded,
+                }),
+        );
+        self.uninstalled.extend(
+            module_run
+                .uninstalled
+                .into_iter()
+                .map(|object_id| (module_name.to_string(), object_id)),
+        );
+        self.matches
+            .extend(
+                module_run
+                    .matches
+                    .into_iter()
+                    .map(|(identifier, object)| MatchedIdentifier {
+                        module: module_name.to_string(),
+                        identifier,
+                        object,
+                    }),
+            );
+
+        match module_run.cancelled {
+            true => Err(Error::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+/// The outcome of a run that completed.
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    /// Number of objects that matched an identifier across all modules, or `None` if the run
+    /// stopped before running any module.
+    pub matched_count: Option<usize>,
+}
+
+pub async fn run(config: Config) -> Result<RunSummary, Error> {
+    set_color_enabled(!config.state.no_color);
+    services::windows::set_ffi_tracing(config.state.trace_ffi);
     print_header();
-    let state = config.state;
+    let mut state = config.state;
+    check_for_newer_release(&state).await;
     let mut modules = config.modules;
 
+    if state.record_last_run || state.retry_failed {
+        state.last_run = LastRun::load(&state.current_path);
+    }
+    if state.retry_failed && !state.last_run.has_failures() {
+        println!("Nothing failed to uninstall on the last run.");
+        return Ok(RunSummary::default());
+    }
+    // never make changes without actually being elevated
+    if state.force_elevated && !state.dry_run && !services::windows::process_is_elevated() {
+        println!("Pretending to be elevated, no changes will be made.");
+        state.dry_run = true;
+    }
+    let state = state;
+
+    if services::windows::is_reboot_pending() {
+        let message = "A reboot is already pending from a previous install or uninstall, results may be unreliable until then.";
+        println!("\nWarning: {}", message);
+        warn!("{}", message);
+
+        if state.interactive
+            && !state.dry_run
+            && terminal::prompt_yes_no("Continue anyway?") != terminal::PromptResult::Yes
+        {
+            println!("Aborting...");
+            return Err(Error::Cancelled);
+        }
+    }
+
     if !state.dry_run && !services::windows::process_is_elevated() {
+        if state.elevate {
+            match services::windows::relaunch_elevated() {
+                Ok(_) => return Ok(RunSummary::default()),
+                Err(err) => eprintln!("{:?}", err),
+            }
+        }
+
         eprintln!("This program must be run as administrator.");
         if state.interactive {
             println!("Press any key to exit...");
             _ = read_key_async(None).await;
-            std::process::exit(1);
+            return Err(Error::NotElevated);
         }
     }
 
+    if state.pick_modules {
+        modules = match pick_modules(modules) {
+            Some(modules) => modules,
+            None => {
+                println!("Aborting...");
+                return Err(Error::Cancelled);
+            }
+        };
+    }
+
+    sort_modules(&mut modules, &state.module_order);
+    prefetch_identifiers(&state, &modules).await;
+
     let mut run_state: RunState = Default::default();
 
     if state.dry_run {
         println!("Running in dry run mode. No changes will be made.");
     }
 
-    for module in modules.iter_mut() {
-        println!("\nRunning '{}'...", module.name());
+    let started = Instant::now();
+    if state.batch_confirm {
+        run_batched(&state, &mut modules, &mut run_state).await?;
+    } else if state.preview {
+        run_previewed(&state, &mut modules, &mut run_state).await?;
+    } else {
+        for module in modules.iter_mut() {
+            let module_run = run_module(&state, module.as_mut()).await;
+            let module_run = run_state.tolerate(&state, module_run)?;
+            run_state.add(module.name(), module_run)?;
+        }
+    }
 
-        match module.run(&state).await {
-            Err(error) => {
-                eprintln!("\n{}", "Error!".red());
-                eprintln!("{:?}", error);
-                eprintln!(
-                    "\nErrors were encountered while running '{}'. Aborting!",
-                    module.name()
-                );
+    if state.clean_orphan_store {
+        clean_orphan_store(&state, &mut run_state);
+    }
 
-                if state.interactive {
-                    println!("Press any key to exit...");
-                    _ = read_key_async(None).await;
-                }
+    if state.verify && !state.dry_run {
+        verify_uninstalled(&modules, &run_state);
+    }
 
-                std::process::exit(1);
-            }
-            Ok(module_run) if module_run.reboot_required => {
-                run_state.need_reboot = true;
-            }
-            Ok(_) => {}
+    let elapsed = started.elapsed();
+    println!("\nAll modules finished in {:.1}s", elapsed.as_secs_f32());
+    info!("All modules finished in {:?}", elapsed);
+
+    let result = match run_state.failed_modules.is_empty() {
+        true => Ok(RunSummary {
+            matched_count: Some(run_state.matched_count),
+        }),
+        false => {
+            let failed_modules = run_state.failed_modules.join(", ");
+            eprintln!("\nThe following modules failed: {}", failed_modules);
+            Err(Error::ModuleFailed(failed_modules))
+        }
+    };
+
+    if let Some(path) = &state.export_matches {
+        match export_matches(path, &run_state.matches) {
+            Ok(_) => println!("Exported matched identifiers into '{}'", path.display()),
+            Err(err) => no_color(|| warn!("Failed to export matches: {:?}", err)),
+        }
+    }
+
+    // a retry records its own outcomes so what succeeded is not retried again
+    if (state.record_last_run || state.retry_failed) && !state.dry_run {
+        let last_run = LastRun::new(std::mem::take(&mut run_state.outcomes));
+        if let Err(err) = last_run.save(&state.current_path) {
+            no_color(|| warn!("Failed to save last run: {:?}", err));
         }
     }
 
-    if run_state.need_reboot {
+    if run_state.need_reboot && !state.allow_reboot {
+        println!("\nReboot is required to complete the cleanup. Please reboot manually.");
+    } else if run_state.need_reboot {
         if state.interactive {
             println!("\nReboot is required to complete the cleanup.");
             println!("Press any key to reboot now, or press 'q' to cancel reboot... ");
 
-            if let WaitResult::Key(key) = read_key_async(None).await.unwrap() {
+            let wait_result = match state.reboot_timeout {
+                Some(seconds) => terminal::read_key_with_countdown(seconds).await,
+                None => read_key_async(None).await,
+            };
+
+            if let WaitResult::Key(key) = wait_result.unwrap() {
                 if key.code == KeyCode::Char('q') {
                     println!("Reboot cancelled.");
-                    return;
+                    return result;
                 }
             }
 
@@ -160,21 +667,398 @@ pub async fn run(config: Config) {
                 .expect("Failed to execute shutdown command.");
         }
 
-        return;
+        return result;
     }
 
     if state.interactive {
         println!("\nCleanup complete. Press any key to exit... ");
         _ = read_key_async(None).await;
     }
+
+    result
+}
+
+/// Fetches the identifiers of the modules about to run ahead of time, see
+/// [`services::identifiers::prefetch_online`].
+async fn prefetch_identifiers(state: &State, modules: &ModuleCollection) {
+    let identifiers = modules.iter().map(|module| module.identifier_file());
+    services::identifiers::prefetch_online(state, identifiers).await;
+}
+
+/// Sorts modules by their position in `order`, falling back to their priority
+/// for modules not present in it.
+fn sort_modules(modules: &mut ModuleCollection, order: &[String]) {
+    modules.sort_by_key(|module| {
+        let position = order.iter().position(|name| name == module.cli_name());
+        (position.unwrap_or(usize::MAX), module.priority())
+    });
+}
+
+async fn find_all_matches(
+    state: &State,
+    modules: &mut ModuleCollection,
+    run_state: &mut RunState,
+) -> Result<Vec<Vec<ModuleMatch>>, Error> {
+    let mut module_matches = Vec::with_capacity(modules.len());
+    for module in modules.iter_mut() {
+        println!("\nSearching with '{}'...", module.name());
+
+        let name = module.name().to_string();
+        let matches = abort_on_error(state, &name, module.find_matches(state).await).await;
+        module_matches.push(run_state.tolerate(state, matches)?);
+    }
+
+    Ok(module_matches)
+}
+
+/// Finds the matches of every module first, so a single confirmation covers the whole run.
+async fn run_previewed(
+    state: &State,
+    modules: &mut ModuleCollection,
+    run_state: &mut RunState,
+) -> Result<(), Error> {
+    let module_matches = find_all_matches(state, modules, run_state).await?;
+    let total: usize = module_matches.iter().map(Vec::len).sum();
+    let summary = format!(
+        "Found {} items to remove across {} modules.",
+        total,
+        modules.len()
+    );
+    info!("{}", summary);
+
+    if state.interactive && !state.dry_run && total > 0 {
+        let prompt = terminal::prompt_yes_no(&format!("\n{} Proceed?", summary));
+        if prompt != terminal::PromptResult::Yes {
+            println!("Aborting...");
+            return Err(Error::Cancelled);
+        }
+    } else {
+        println!("\n{}", summary);
+    }
+
+    for (module, matches) in modules.iter().zip(module_matches) {
+        println!("\nRunning '{}'...", module.name());
+
+        let module_started = Instant::now();
+        run_module_hook(state, &state.pre_module_hook, module.as_ref());
+        let module_run = module.run_matches(matches, state).await;
+        run_module_hook(state, &state.post_module_hook, module.as_ref());
+        let module_run = abort_on_error(state, module.name(), module_run).await;
+        let module_run = run_state.tolerate(state, module_run)?;
+        report_module_duration(module.name(), module_started.elapsed());
+        run_state.add(module.name(), module_run)?;
+    }
+
+    Ok(())
 }
 
-pub async fn dump(config: Config) {
+async fn run_module(state: &State, module: &mut dyn Module) -> Result<ModuleRunInfo, Error> {
+    println!("\nRunning '{}'...", module.name());
+
+    let name = module.name().to_string();
+    let module_started = Instant::now();
+    run_module_hook(state, &state.pre_module_hook, module);
+    let module_run = module.run(state).await;
+    run_module_hook(state, &state.post_module_hook, module);
+    let module_run = abort_on_error(state, &name, module_run).await?;
+    report_module_duration(&name, module_started.elapsed());
+    Ok(module_run)
+}
+
+/// Runs a `--pre-module-hook` or `--post-module-hook` command through `cmd /C` with the cli
+/// name of the module appended. A failing hook is reported but does not stop the run.
+fn run_module_hook(state: &State, hook: &Option<String>, module: &dyn Module) {
+    use std::os::windows::process::CommandExt;
+
+    let Some(hook) = hook else {
+        return;
+    };
+    let command = format!("{} {}", hook, module.cli_name());
+
+    if state.dry_run {
+        println!("Would run hook '{}'", command);
+        return;
+    }
+
+    println!("Running hook '{}'...", command);
+    match std::process::Command::new("cmd")
+        .raw_arg(format!("/C {}", command))
+        .status()
+    {
+        Ok(status) if status.success() => info!("Hook '{}' exited with {}", command, status),
+        Ok(status) => {
+            let message = format!("Hook '{}' exited with {}", command, status);
+            println!("{}", paint(&message, Color::Yellow));
+            warn!("{}", message);
+        }
+        Err(err) => {
+            let message = format!("Failed to run hook '{}': {}", command, err);
+            eprintln!("{}", paint(&message, Color::Red));
+            warn!("{}", message);
+        }
+    }
+}
+
+async fn run_batched(
+    state: &State,
+    modules: &mut ModuleCollection,
+    run_state: &mut RunState,
+) -> Result<(), Error> {
+    let module_matches = find_all_matches(state, modules, run_state).await?;
+
+    let descriptions: Vec<String> = modules
+        .iter()
+        .zip(&module_matches)
+        .flat_map(|(module, matches)| {
+            matches
+                .iter()
+                .map(move |module_match| format!("{} ({})", module_match, module.name()))
+        })
+        .collect();
+
+    if descriptions.is_empty() {
+        println!("\nNothing to uninstall is found.");
+        return Ok(());
+    }
+
+    println!("\nThe following will be uninstalled:");
+    for (i, description) in descriptions.iter().enumerate() {
+        println!("  [{}] {}", i + 1, description);
+    }
+
+    let selection = if state.interactive && !state.dry_run {
+        match terminal::prompt_deselect(descriptions.len()) {
+            Some(selection) => selection,
+            None => {
+                println!("Aborting...");
+                return Err(Error::Cancelled);
+            }
+        }
+    } else {
+        vec![true; descriptions.len()]
+    };
+
+    let mut selection = selection.into_iter();
+    for (module, matches) in modules.iter().zip(module_matches) {
+        let matches: Vec<_> = matches
+            .into_iter()
+            .filter(|_| selection.next().unwrap())
+            .collect();
+
+        if matches.is_empty() {
+            continue;
+        }
+
+        println!("\nRunning '{}'...", module.name());
+
+        let module_started = Instant::now();
+        run_module_hook(state, &state.pre_module_hook, module.as_ref());
+        let module_run = module.uninstall_matches(matches, state).await;
+        run_module_hook(state, &state.post_module_hook, module.as_ref());
+        let module_run = abort_on_error(state, module.name(), module_run).await;
+        let module_run = run_state.tolerate(state, module_run)?;
+        report_module_duration(module.name(), module_started.elapsed());
+        run_state.add(module.name(), module_run)?;
+    }
+
+    // everything found counts, not only what was selected
+    run_state.matched_count = descriptions.len();
+    Ok(())
+}
+
+fn report_module_duration(module_name: &str, duration: Duration) {
+    println!(
+        "'{}' finished in {:.1}s",
+        module_name,
+        duration.as_secs_f32()
+    );
+    info!("'{}' finished in {:?}", module_name, duration);
+}
+
+async fn abort_on_error<T>(
+    state: &State,
+    module_name: &str,
+    result: error_stack::Result<T, ModuleError>,
+) -> Result<T, Error> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(error) => {
+            eprintln!("\n{}", paint("Error!", Color::Red));
+            eprintln!("{:?}", error);
+            if state.continue_on_error {
+                eprintln!(
+                    "\nErrors were encountered while running '{}'. Continuing with the next module.",
+                    module_name
+                );
+                return Err(Error::ModuleFailed(module_name.to_string()));
+            }
+
+            eprintln!(
+                "\nErrors were encountered while running '{}'. Aborting!",
+                module_name
+            );
+
+            if state.interactive {
+                println!("Press any key to exit...");
+                _ = read_key_async(None).await;
+            }
+
+            Err(Error::ModuleFailed(module_name.to_string()))
+        }
+    }
+}
+
+/// Checks that every object uninstalled during the run is actually gone.
+fn verify_uninstalled(modules: &[Box<dyn Module>], run_state: &RunState) {
+    println!("\nVerifying that uninstalled objects are gone...");
+    let mut survivors_found = false;
+
+    for module in modules {
+        let uninstalled: Vec<&str> = run_state
+            .uninstalled
+            .iter()
+            .filter(|(module_name, _)| module_name == module.name())
+            .map(|(_, object_id)| object_id.as_str())
+            .collect();
+
+        if uninstalled.is_empty() {
+            continue;
+        }
+
+        match module.find_survivors(&uninstalled) {
+            Ok(survivors) => {
+                for survivor in survivors {
+                    survivors_found = true;
+                    let message = format!("'{}' is still present after uninstalling", survivor);
+                    println!("  {}", paint(&message, Color::Yellow));
+                    warn!("{}", message);
+                }
+            }
+            Err(err) => {
+                eprintln!("{:?}", err);
+                no_color(|| warn!("{:?}", err));
+            }
+        }
+    }
+
+    match survivors_found {
+        true => println!("Reboot and run again to remove them, or remove them manually."),
+        false => println!("Every uninstalled object is gone."),
+    }
+}
+
+/// Removes driver store folders left behind by INFs that are no longer published.
+fn clean_orphan_store(state: &State, run_state: &mut RunState) {
+    use services::interest::is_of_interest_iter as candidate_iter;
+    const NAME: &str = "Orphaned driver store cleanup";
+
+    println!("\nLooking for orphaned driver store folders...");
+    let folders = match services::windows::enumerate_orphaned_store_folders() {
+        Ok(folders) => folders,
+        Err(err) => {
+            eprintln!("{:?}", err);
+            no_color(|| warn!("{:?}", err));
+            run_state.failed_modules.push(NAME.to_string());
+            return;
+        }
+    };
+
+    let folders: Vec<_> = folders
+        .into_iter()
+        .filter(|f| candidate_iter([Some(f.name()), f.provider()].into_iter().flatten()))
+        .collect();
+
+    if folders.is_empty() {
+        println!("No orphaned driver store folder is found.");
+        return;
+    }
+
+    let summary = format!("{}: {} matches", NAME, folders.len());
+    println!("{}", summary);
+    info!("{}", summary);
+    run_state.matched_count += folders.len();
+
+    let mut failed = false;
+    for folder in folders {
+        if state.interactive && !state.dry_run {
+            match terminal::prompt_yes_no(&format!("Remove '{}'?", folder)) {
+                terminal::PromptResult::Yes => {}
+                terminal::PromptResult::Cancel => break,
+                _ => continue,
+            }
+        }
+
+        let message = format!("Removing '{}'...", folder);
+        println!("  {}", paint(message, Color::Cyan));
+        if state.dry_run {
+            continue;
+        }
+
+        match services::windows::uninstall_inf(folder.inf_path()) {
+            Ok(reboot) => {
+                run_state.need_reboot |= reboot;
+                let message = format!("Removed '{}'", folder);
+                println!("  {}", paint(message, Color::Green));
+            }
+            Err(code) => {
+                failed = true;
+                let message = format!("Failed to remove '{}' ({:?})", folder, code);
+                eprintln!("  {}", paint(message, Color::Red));
+                warn!("{}", message);
+            }
+        }
+    }
+
+    if failed {
+        run_state.failed_modules.push(NAME.to_string());
+    }
+}
+
+/// Prints what every module would uninstall, without uninstalling or dumping anything.
+pub async fn list_matches(config: Config) -> Result<(), Error> {
+    set_color_enabled(!config.state.no_color);
+    services::windows::set_ffi_tracing(config.state.trace_ffi);
     print_header();
-    println!("\nDumping into {}...", config.state.current_path.display());
+    let state = config.state;
+    let mut modules = config.modules;
 
+    sort_modules(&mut modules, &state.module_order);
+    prefetch_identifiers(&state, &modules).await;
+    let mut run_state = RunState::default();
+    let module_matches = find_all_matches(&state, &mut modules, &mut run_state).await?;
+
+    for (module, matches) in modules.iter().zip(&module_matches) {
+        println!("\n{}: {} matches", module.name(), matches.len());
+        for module_match in matches {
+            println!("  {}", module_match);
+        }
+    }
+
+    Ok(())
+}
+
+/// Dumps information about the system, returning an error naming the dumps that failed.
+pub async fn dump(config: Config) -> Result<(), Error> {
+    set_color_enabled(!config.state.no_color);
+    services::windows::set_ffi_tracing(config.state.trace_ffi);
     let (state, modules) = (config.state, config.modules);
 
+    // only the dumps go to stdout, so they can be copied as they are
+    if !state.dump_stdout {
+        print_header();
+        check_for_newer_release(&state).await;
+
+        let dump_dir = match cleanup_modules::create_dump_dir(&state) {
+            Ok(dump_dir) => dump_dir,
+            Err(err) => {
+                eprintln!("{:?}", err);
+                return Err(Error::NothingDumped);
+            }
+        };
+        println!("\nDumping into {}...", dump_dir.display());
+    }
+
+    let mut failed_dumps = Vec::new();
     for module in modules.iter() {
         let dumper = match module.get_dumper() {
             Some(dumper) => dumper,
@@ -184,38 +1068,446 @@ pub async fn dump(config: Config) {
         let result = dumper.dump(&state).await;
         if let Err(err) = result {
             eprintln!("{:?}", err);
-            eprintln!()
+            eprintln!();
+            failed_dumps.push(module.name().to_string());
         }
     }
+
+    if let Err(err) = dump_os_info(&state) {
+        eprintln!("{:?}", err);
+        eprintln!();
+        failed_dumps.push("OS info".to_string());
+    }
+
+    if state.correlate {
+        if let Err(err) = dump_correlation(&state) {
+            eprintln!("{:?}", err);
+            eprintln!();
+            failed_dumps.push("correlation".to_string());
+        }
+    }
+
+    if state.zip_dumps {
+        match zip_dumps(&state) {
+            Ok(zip_path) => println!("\nPackaged dumps into '{}'", zip_path.display()),
+            Err(err) => {
+                eprintln!("{:?}", err);
+                failed_dumps.push("zip".to_string());
+            }
+        }
+    }
+
+    match failed_dumps.is_empty() {
+        true => Ok(()),
+        false => {
+            let failed_dumps = failed_dumps.join(", ");
+            eprintln!("\nThe following dumps failed: {}", failed_dumps);
+            Err(Error::DumpFailed(failed_dumps))
+        }
+    }
+}
+
+/// Packages the dumps written during this run and the log into a timestamped zip file, ready
+/// to be attached to an issue. Other files in the dump directory are never included, as it
+/// may be shared with unrelated files through `--dump-path`.
+fn zip_dumps(state: &State) -> error_stack::Result<PathBuf, std::io::Error> {
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let zip_path = state
+        .current_path
+        .join(format!("tdc-dump-{}.zip", timestamp));
+
+    let mut files = cleanup_modules::written_dumps();
+    files.push(PathBuf::from("log.txt"));
+
+    write_zip(&zip_path, &files)
+        .into_report()
+        .attach_printable_lazy(|| format!("cannot create '{}'", zip_path.display()))?;
+
+    Ok(zip_path)
+}
+
+fn write_zip(zip_path: &Path, files: &[PathBuf]) -> std::io::Result<()> {
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut zip = zip::ZipWriter::new(File::create(zip_path)?);
+
+    for path in files.iter().filter(|path| path.exists()) {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        zip.start_file(name, options)?;
+        std::io::copy(&mut File::open(path)?, &mut zip)?;
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+fn export_matches(
+    path: &Path,
+    matches: &[MatchedIdentifier],
+) -> error_stack::Result<(), std::io::Error> {
+    let file = File::create(path)
+        .into_report()
+        .attach_printable_lazy(|| format!("cannot create file '{}'", path.display()))?;
+
+    serde_json::to_writer_pretty(file, matches)
+        .map_err(std::io::Error::from)
+        .into_report()
+        .attach_printable_lazy(|| format!("failed to write '{}'", path.display()))
+}
+
+fn dump_os_info(state: &State) -> error_stack::Result<(), std::io::Error> {
+    let os_info = services::windows::get_os_info();
+    let file_path = cleanup_modules::emit_dump(state, "os-info.json", |writer| {
+        serde_json::to_writer_pretty(writer, &os_info)
+    })?;
+
+    if let Some(file_path) = file_path {
+        println!("Dumped OS info into '{}'", file_path.display());
+    }
+
+    Ok(())
+}
+
+/// Dumps drivers grouped with the devices and driver packages related to them.
+fn dump_correlation(state: &State) -> error_stack::Result<(), CorrelationError> {
+    let devices = services::windows::enumerate_devices().change_context(CorrelationError)?;
+    let drivers = services::windows::enumerate_drivers().change_context(CorrelationError)?;
+    let driver_packages =
+        services::windows::enumerate_driver_packages().change_context(CorrelationError)?;
+
+    let correlations = correlation::correlate(&devices, &drivers, &driver_packages);
+
+    let file_path = cleanup_modules::emit_dump(state, "correlation.json", |writer| {
+        serde_json::to_writer_pretty(writer, &correlations)
+    })
+    .change_context(CorrelationError)?;
+
+    if let Some(file_path) = file_path {
+        println!("Dumped correlation into '{}'", file_path.display());
+    }
+
+    Ok(())
+}
+
+/// Names of the identifier files, as accepted by `--refresh`.
+pub fn identifier_files() -> impl Iterator<Item = &'static str> {
+    services::identifiers::embedded_names()
+}
+
+pub fn test_interest(string: &str) {
+    let (interests, counter_interests) = services::interest::get_interest_matches(string);
+    let is_of_interest = services::interest::is_of_interest(Some(string));
+
+    println!("Input: '{}'", string);
+    println!("Matching keywords: {:?}", interests);
+    println!("Counter-matching keywords: {:?}", counter_interests);
+    println!("Of interest: {}", is_of_interest);
+}
+
+/// Prints every property read for a single device as JSON, to help write identifiers for it
+/// without dumping the whole system.
+pub fn inspect_device(instance_id: &str) -> Result<(), Error> {
+    match services::windows::inspect_device(instance_id) {
+        Ok(inspection) => {
+            println!("{}", serde_json::to_string_pretty(&inspection).unwrap());
+            Ok(())
+        }
+        Err(err) => {
+            eprintln!("{:?}", err);
+            Err(Error::DumpFailed(format!("device '{}'", instance_id)))
+        }
+    }
+}
+
+/// Compiles the interest patterns, so an invalid one is reported before anything runs.
+pub fn check_interests() -> Result<(), Error> {
+    services::interest::verify().map_err(|err| Error::InvalidInterest(err.to_string()))
+}
+
+/// Checks that the embedded identifiers of every module parse and compile.
+/// Returns `false` if any of them failed.
+pub fn self_test(modules: &[Box<dyn Module>]) -> bool {
+    let mut passed = true;
+
+    match check_interests() {
+        Ok(_) => println!("Interest patterns OK"),
+        Err(err) => {
+            passed = false;
+            eprintln!("{}", err);
+        }
+    }
+
+    for module in modules {
+        match module.self_test() {
+            Ok(count) => println!("{}: {} identifiers OK", module.name(), count),
+            Err(err) => {
+                passed = false;
+                eprintln!("{:?}", err);
+            }
+        }
+    }
+
+    passed
+}
+
+/// Lets the user toggle the modules to run, all of them checked at first. Returns `None` if
+/// the user aborts.
+fn pick_modules(modules: ModuleCollection) -> Option<ModuleCollection> {
+    let names: Vec<&str> = modules.iter().map(|module| module.name()).collect();
+    let selection = terminal::prompt_checklist("\nSelect the modules to run", &names)?;
+
+    Some(
+        modules
+            .into_iter()
+            .zip(selection)
+            .filter_map(|(module, selected)| selected.then_some(module))
+            .collect(),
+    )
 }
 
 fn print_header() {
-    println!("TabletDriverCleanup v{}", env!("CARGO_PKG_VERSION"));
+    match services::identifiers::embedded_version() {
+        Some(version) => println!(
+            "TabletDriverCleanup v{} (identifiers v{})",
+            env!("CARGO_PKG_VERSION"),
+            version
+        ),
+        None => println!("TabletDriverCleanup v{}", env!("CARGO_PKG_VERSION")),
+    }
+    let os_info = services::windows::get_os_info();
+    println!("Running on {}", os_info);
+
+    if os_info.is_emulated() {
+        let message = format!(
+            "Running the {} build under emulation on {} Windows, some devices and drivers may not be detected correctly.",
+            os_info.process_arch().unwrap(),
+            os_info.native_arch().unwrap()
+        );
+        println!("Warning: {}", message);
+        warn!("{}", message);
+    }
 }
 
-pub fn parse_to_config(modules: Vec<Box<dyn Module>>, matches: ArgMatches) -> Config {
+/// Prints a notice when a newer release is available. Failures are only logged.
+async fn check_for_newer_release(state: &State) {
+    if !state.version_check || !state.allow_updates {
+        return;
+    }
+
+    match services::release::get_newer_release(state).await {
+        Ok(Some(tag)) => println!(
+            "A newer version ({}) is available at https://github.com/X9VoiD/TabletDriverCleanup/releases",
+            tag
+        ),
+        Ok(None) => {}
+        Err(err) => no_color(|| warn!("{:?}", err)),
+    }
+}
+
+pub fn parse_to_config(
+    modules: Vec<Box<dyn Module>>,
+    matches: ArgMatches,
+) -> Result<Config, Error> {
     let mut current_path: PathBuf = std::env::args().next().unwrap().into();
     current_path.pop();
 
+    // --clean turns on everything that was not explicitly turned off on the command line,
+    // regardless of what the defaults of these flags become
+    let clean = matches.get_flag(constants::CLEAN);
+    let enabled = |name: &str| match matches.value_source(name) {
+        Some(ValueSource::CommandLine) => matches.get_flag(name),
+        _ => clean || matches.get_flag(name),
+    };
+
     let mut builder = ConfigBuilder::new()
         .current_path(current_path)
         .dry_run(matches.get_flag(constants::DRY_RUN))
-        .interactive(matches.get_flag(constants::INTERACTIVE))
-        .use_cache(matches.get_flag(constants::USE_CACHE))
-        .allow_updates(matches.get_flag(constants::ALLOW_UPDATES));
+        .interactive(enabled(constants::INTERACTIVE))
+        .use_cache(enabled(constants::USE_CACHE))
+        .allow_updates(enabled(constants::ALLOW_UPDATES))
+        .batch_confirm(matches.get_flag(constants::BATCH_CONFIRM))
+        .elevate(matches.get_flag(constants::ELEVATE))
+        .allow_reboot(matches.get_flag(constants::ALLOW_REBOOT))
+        .vendor(matches.get_one::<String>(constants::VENDOR).cloned())
+        .module_order(
+            matches
+                .get_many::<String>(constants::MODULE_ORDER)
+                .map(|order| order.cloned().collect())
+                .unwrap_or_default(),
+        )
+        .stop_processes(matches.get_flag(constants::STOP_PROCESSES))
+        .dump_path(matches.get_one::<PathBuf>(constants::DUMP_PATH).cloned())
+        .ghost_only(matches.get_flag(constants::GHOST_ONLY))
+        .proxy(matches.get_one::<String>(constants::PROXY).cloned())
+        .version_check(matches.get_flag(constants::VERSION_CHECK))
+        .zip_dumps(matches.get_flag(constants::ZIP_DUMPS))
+        .reboot_timeout(matches.get_one::<u64>(constants::REBOOT_TIMEOUT).copied())
+        .no_color(
+            matches.get_flag(constants::NO_COLOR)
+                || std::env::var_os("NO_COLOR").map_or(false, |v| !v.is_empty()),
+        )
+        .preview(matches.get_flag(constants::PREVIEW))
+        .record_last_run(matches.get_flag(constants::LAST_RUN))
+        .include_classes(
+            matches
+                .get_many::<Uuid>(constants::INCLUDE_CLASS)
+                .map(|classes| classes.copied().collect())
+                .unwrap_or_default(),
+        )
+        .strict_identifiers(matches.get_flag(constants::STRICT_IDENTIFIERS))
+        .correlate(matches.get_flag(constants::CORRELATE))
+        .retry_failed(matches.get_flag(constants::RETRY_FAILED))
+        .delegate_delay(
+            matches
+                .get_one::<u64>(constants::DELEGATE_DELAY)
+                .map(|ms| Duration::from_millis(*ms)),
+        )
+        .excluded_instances(
+            matches
+                .get_many::<String>(constants::EXCLUDE_INSTANCE)
+                .map(|excluded| excluded.cloned().collect())
+                .unwrap_or_default(),
+        )
+        .continue_on_error(matches.get_one::<bool>(constants::FAIL_FAST) == Some(&false))
+        .keep_cache_days(matches.get_one::<u64>(constants::KEEP_CACHE_DAYS).copied())
+        .trace_ffi(matches.get_flag(constants::TRACE_FFI))
+        .clean_orphan_store(matches.get_flag(constants::CLEAN_ORPHAN_STORE))
+        .dump_format(
+            match matches
+                .get_one::<String>(constants::DUMP_FORMAT)
+                .map(String::as_str)
+            {
+                Some("jsonl") => DumpFormat::Jsonl,
+                _ => DumpFormat::Json,
+            },
+        )
+        .verify(matches.get_flag(constants::VERIFY))
+        .since(matches.get_one::<NaiveDate>(constants::SINCE).copied())
+        .exclude_undated(matches.get_flag(constants::EXCLUDE_UNDATED))
+        .dump_stdout(matches.get_flag(constants::DUMP_STDOUT))
+        .pre_module_hook(
+            matches
+                .get_one::<String>(constants::PRE_MODULE_HOOK)
+                .cloned(),
+        )
+        .post_module_hook(
+            matches
+                .get_one::<String>(constants::POST_MODULE_HOOK)
+                .cloned(),
+        )
+        .export_matches(
+            matches
+                .get_one::<PathBuf>(constants::EXPORT_MATCHES)
+                .cloned(),
+        )
+        .force_elevated(
+            std::env::var_os(constants::FORCE_ELEVATED_ENV).map_or(false, |v| !v.is_empty()),
+        )
+        .refresh_identifiers(
+            matches
+                .get_many::<String>(constants::REFRESH)
+                .map(|identifiers| identifiers.cloned().collect())
+                .unwrap_or_default(),
+        );
+
+    let module_list = match matches.get_one::<PathBuf>(constants::MODULES_FROM) {
+        Some(path) => Some(read_module_list(path, &modules)?),
+        None => None,
+    };
+
+    // modules named on the command line or in the module list run even if their identifiers
+    // disable them
+    let enable_list: Vec<&String> = matches
+        .get_many::<String>(constants::ENABLE)
+        .map(|enabled| enabled.collect())
+        .unwrap_or_default();
+    let force_enabled = modules
+        .iter()
+        .filter(|module| {
+            let name = module.cli_name();
+            enable_list.iter().any(|n| *n == name)
+                || module_list
+                    .as_ref()
+                    .map_or(false, |list| list.iter().any(|n| n == name))
+        })
+        .map(|module| module.name().to_string())
+        .collect();
+    builder = builder.force_enabled(force_enabled);
+
+    // without any module flags, interactive runs let the user pick the modules instead
+    let module_flags_given = modules
+        .iter()
+        .any(|module| matches.value_source(module.cli_name()) == Some(ValueSource::CommandLine));
+    builder = builder.pick_modules(
+        enabled(constants::INTERACTIVE) && !clean && module_list.is_none() && !module_flags_given,
+    );
 
     for module in modules {
         let name = module.cli_name();
-        if matches.get_flag(name) {
+        let listed = module_list
+            .as_ref()
+            .map_or(true, |list| list.iter().any(|n| n == name));
+
+        if enabled(name) && listed {
             builder = builder.add_module(module);
         }
     }
 
-    builder.build()
+    Ok(builder.build())
+}
+
+/// Reads the cli names of the modules to run from a newline or comma-separated file.
+fn read_module_list(path: &Path, modules: &[Box<dyn Module>]) -> Result<Vec<String>, Error> {
+    let content = std::fs::read_to_string(path).map_err(|err| {
+        Error::InvalidModuleList(format!("cannot read {}: {}", path.display(), err))
+    })?;
+
+    let names: Vec<String> = content
+        .split(|c| c == ',' || c == '\n')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect();
+
+    match names
+        .iter()
+        .find(|name| !modules.iter().any(|m| m.cli_name() == name.as_str()))
+    {
+        Some(unknown) => Err(Error::InvalidModuleList(format!(
+            "unknown module '{}' in {}",
+            unknown,
+            path.display()
+        ))),
+        None => Ok(names),
+    }
+}
+
+static COLOR_ENABLED: AtomicBool = AtomicBool::new(true);
+
+fn set_color_enabled(enabled: bool) {
+    COLOR_ENABLED.store(enabled, Ordering::Relaxed);
+    Report::set_color_mode(default_color_mode());
+}
+
+fn default_color_mode() -> ColorMode {
+    match COLOR_ENABLED.load(Ordering::Relaxed) {
+        true => ColorMode::default(),
+        false => ColorMode::None,
+    }
+}
+
+/// Colors `text` for the console, unless colors are disabled.
+fn paint(text: impl std::fmt::Display, color: Color) -> String {
+    match COLOR_ENABLED.load(Ordering::Relaxed) {
+        true => text.to_string().with(color).to_string(),
+        false => text.to_string(),
+    }
 }
 
 fn no_color(action: impl FnOnce()) {
     Report::set_color_mode(ColorMode::None);
     action();
-    Report::set_color_mode(ColorMode::default());
+    Report::set_color_mode(default_color_mode());
 }
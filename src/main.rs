@@ -1,3 +1,4 @@
+use clap::builder::PossibleValuesParser;
 use clap::{command, Arg, ArgAction, Command};
 use simplelog::{self, WriteLogger};
 use tabletdrivercleanup::cleanup_modules::*;
@@ -5,17 +6,12 @@ use tabletdrivercleanup::*;
 
 #[tokio::main]
 async fn main() {
-    WriteLogger::init(
-        simplelog::LevelFilter::Info,
-        simplelog::Config::default(),
-        std::fs::File::create("log.txt").unwrap(),
-    )
-    .unwrap();
-
     let modules: Vec<Box<dyn Module>> = vec![
         Box::new(DriverPackageCleanupModule::new()),
         Box::new(DeviceCleanupModule::new()),
+        Box::new(FilterCleanupModule::new()),
         Box::new(DriverCleanupModule::new()),
+        Box::new(LeftoverCleanupModule::new()),
     ];
 
     let command = command!()
@@ -35,6 +31,89 @@ async fn main() {
                 .action(ArgAction::SetTrue)
                 .required(false),
         )
+        .arg(
+            Arg::new(constants::DUMP_PATH)
+                .long("dump-path")
+                .value_name("DIR")
+                .help("Directory to write dumps to, instead of 'dumps' next to the executable")
+                .value_parser(clap::value_parser!(std::path::PathBuf))
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::DUMP_FORMAT)
+                .long("dump-format")
+                .value_name("FORMAT")
+                .help("Write dumps as a pretty JSON array, or as one JSON object per line")
+                .value_parser(["json", "jsonl"])
+                .default_value("json")
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::SINCE)
+                .long("since")
+                .value_name("YYYYMMDD")
+                .help("Only dump driver packages installed on or after this date")
+                .value_parser(|date: &str| chrono::NaiveDate::parse_from_str(date, "%Y%m%d"))
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::EXCLUDE_UNDATED)
+                .long("exclude-undated")
+                .help("With --since, also leave out driver packages without an install date")
+                .requires(constants::SINCE)
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::DUMP_STDOUT)
+                .long("dump-stdout")
+                .help("Print the dumps to the console instead of writing them to files")
+                .requires(constants::DUMP)
+                .conflicts_with_all([constants::ZIP_DUMPS, constants::DUMP_PATH])
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::ZIP_DUMPS)
+                .long("zip-dumps")
+                .help("Package this run's dumps and log into a timestamped tdc-dump-<time>.zip")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::CORRELATE)
+                .long("correlate")
+                .help("Also dump drivers grouped with their devices and driver packages")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::GHOST_ONLY)
+                .long("ghost-only")
+                .help("Only dump devices that are not present on the system")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::VENDOR)
+                .long("vendor")
+                .value_name("REGEX")
+                .help("Only dump objects whose manufacturer, provider or publisher matches")
+                .value_parser(|s: &str| regex::Regex::new(s).map(|_| s.to_string()))
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::CLEAN)
+                .long("clean")
+                .help("Run all modules with prompts, cache and updates. --no-* flags still apply")
+                .conflicts_with_all([constants::DUMP, constants::MODULES_FROM])
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
         .arg(
             Arg::new(constants::INTERACTIVE)
                 .long("no-prompt")
@@ -51,6 +130,15 @@ async fn main() {
                 .action(ArgAction::SetFalse)
                 .required(false),
         )
+        .arg(
+            Arg::new(constants::KEEP_CACHE_DAYS)
+                .long("keep-cache-days")
+                .value_name("DAYS")
+                .help("Ignore cached identifiers older than this many days")
+                .value_parser(clap::value_parser!(u64))
+                .action(ArgAction::Set)
+                .required(false),
+        )
         .arg(
             Arg::new(constants::ALLOW_UPDATES)
                 .long("no-update")
@@ -58,20 +146,350 @@ async fn main() {
                 .help("Do not check online for identifier updates")
                 .action(ArgAction::SetFalse)
                 .required(false),
+        )
+        .arg(
+            Arg::new(constants::REFRESH)
+                .long("refresh")
+                .value_name("FILE")
+                .help("Fetch this identifier file online even if it is cached. Can be repeated")
+                .value_parser(PossibleValuesParser::new(identifier_files()))
+                .action(ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::PROXY)
+                .long("proxy")
+                .value_name("URL")
+                .help("Proxy to use when checking online for identifier updates")
+                .value_parser(|s: &str| reqwest::Proxy::all(s).map(|_| s.to_string()))
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::VERSION_CHECK)
+                .long("version-check")
+                .help("Check online for a newer release of TabletDriverCleanup")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::ALLOW_REBOOT)
+                .long("no-reboot")
+                .help("Do not reboot after cleanup, only report if one is required")
+                .action(ArgAction::SetFalse)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::REBOOT_TIMEOUT)
+                .long("reboot-timeout")
+                .value_name("SECONDS")
+                .help("Reboot automatically if no key is pressed at the reboot prompt within this time")
+                .value_parser(clap::value_parser!(u64))
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::EXCLUDE_INSTANCE)
+                .long("exclude-instance")
+                .value_name("ID")
+                .help("Keep devices whose instance id contains this. Can be repeated")
+                .action(ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::NOTHING_FOUND_CODE)
+                .long("nothing-found-code")
+                .help("Exit with code 2 instead of 0 when no module finds anything to uninstall")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::FAIL_FAST)
+                .long("fail-fast")
+                .value_name("BOOL")
+                .help("Stop at the first module that fails. With false, run the others and report failures at the end")
+                .value_parser(clap::value_parser!(bool))
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("true")
+                .default_value("true")
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::STRICT_IDENTIFIERS)
+                .long("strict-identifiers")
+                .help("Abort when an identifier file fails to load, instead of skipping its module")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::DELEGATE_DELAY)
+                .long("delegate-delay")
+                .value_name("MS")
+                .help("How long to wait for an uninstaller to hand off to another process [default: 500]")
+                .value_parser(clap::value_parser!(u64))
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::STOP_PROCESSES)
+                .long("stop-processes")
+                .help("Stop vendor processes running from a driver package before uninstalling it")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::BATCH_CONFIRM)
+                .long("batch-confirm")
+                .help("Confirm all uninstalls at once before executing them")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::PREVIEW)
+                .long("preview")
+                .help("Count what would be uninstalled across all modules and confirm once before starting")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::LAST_RUN)
+                .long("last-run")
+                .help("Record uninstall outcomes in last-run.json and flag objects that failed last time")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::RETRY_FAILED)
+                .long("retry-failed")
+                .help("Only retry what failed to uninstall on the last run recorded with --last-run")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::VERIFY)
+                .long("verify")
+                .help("Check that everything uninstalled is actually gone after the run")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::CLEAN_ORPHAN_STORE)
+                .long("clean-orphan-store")
+                .help("Also remove tablet driver store folders whose INF is no longer published")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::PRE_MODULE_HOOK)
+                .long("pre-module-hook")
+                .value_name("CMD")
+                .help("Run this command before each module, with the module cli name appended")
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::POST_MODULE_HOOK)
+                .long("post-module-hook")
+                .value_name("CMD")
+                .help("Run this command after each module, with the module cli name appended")
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::EXPORT_MATCHES)
+                .long("export-matches")
+                .value_name("FILE")
+                .help("Write the identifiers that matched during the run and what they matched as JSON")
+                .value_parser(clap::value_parser!(std::path::PathBuf))
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::ELEVATE)
+                .long("elevate")
+                .help("Relaunch as administrator if not already elevated")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::MODULE_ORDER)
+                .long("order")
+                .value_name("MODULES")
+                .help("Comma-separated list of module cli names to run first, in order")
+                .value_delimiter(',')
+                .value_parser(PossibleValuesParser::new(
+                    modules.iter().map(|m| m.cli_name().to_string()),
+                ))
+                .action(ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::ENABLE)
+                .long("enable")
+                .value_name("MODULES")
+                .help("Comma-separated list of module cli names to run even if disabled by their identifiers")
+                .value_delimiter(',')
+                .value_parser(PossibleValuesParser::new(
+                    modules.iter().map(|m| m.cli_name().to_string()),
+                ))
+                .action(ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::MODULES_FROM)
+                .long("modules-from")
+                .value_name("FILE")
+                .help("Only run the modules listed in this file, separated by commas or newlines")
+                .value_parser(clap::value_parser!(std::path::PathBuf))
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::INCLUDE_CLASS)
+                .long("include-class")
+                .value_name("GUID")
+                .help("Dump every device and driver in this class regardless of interest. Can be repeated")
+                .value_parser(|s: &str| uuid::Uuid::parse_str(s))
+                .action(ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::NO_COLOR)
+                .long("no-color")
+                .help("Do not use colors in the output. Also enabled by the NO_COLOR environment variable")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::LIST_MATCHES)
+                .long("list-matches")
+                .help("List what each module would uninstall, then exit without changing anything")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::LIST_MODULES)
+                .long("list-modules")
+                .help("List the available modules, then exit")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::TRACE_FFI)
+                .long("trace-ffi")
+                .help("Log the raw results of every Windows property lookup to log.txt")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::SELF_TEST)
+                .long("self-test")
+                .help("Check that the embedded identifiers parse and compile, then exit")
+                .action(ArgAction::SetTrue)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::INSPECT_DEVICE)
+                .long("inspect-device")
+                .value_name("ID")
+                .help("Print every property read for this device as JSON, then exit")
+                .action(ArgAction::Set)
+                .required(false),
+        )
+        .arg(
+            Arg::new(constants::TEST_INTEREST)
+                .long("test-interest")
+                .value_name("STRING")
+                .help("Print the interest keywords matching a string, then exit")
+                .action(ArgAction::Set)
+                .hide(true)
+                .required(false),
         );
 
     let matches = add_modules_to_command(command, &modules).get_matches();
-    let mode = match matches.get_flag("dump") {
-        true => Mode::Dump,
-        false => Mode::Run,
+    let log_level = match matches.get_flag(constants::TRACE_FFI) {
+        true => simplelog::LevelFilter::Debug,
+        false => simplelog::LevelFilter::Info,
+    };
+
+    WriteLogger::init(
+        log_level,
+        simplelog::Config::default(),
+        std::fs::File::create("log.txt").unwrap(),
+    )
+    .unwrap();
+
+    if let Err(err) = tabletdrivercleanup::check_interests() {
+        eprintln!("{}", err);
+        std::process::exit(err.exit_code());
+    }
+
+    if matches.get_flag(constants::LIST_MODULES) {
+        list_modules(&modules);
+        return;
+    }
+
+    if matches.get_flag(constants::SELF_TEST) {
+        let passed = tabletdrivercleanup::self_test(&modules);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    if let Some(instance_id) = matches.get_one::<String>(constants::INSPECT_DEVICE) {
+        if let Err(err) = tabletdrivercleanup::inspect_device(instance_id) {
+            std::process::exit(err.exit_code());
+        }
+        return;
+    }
+
+    if let Some(string) = matches.get_one::<String>(constants::TEST_INTEREST) {
+        tabletdrivercleanup::test_interest(string);
+        return;
+    }
+
+    let mode = if matches.get_flag(constants::DUMP) {
+        Mode::Dump
+    } else if matches.get_flag(constants::LIST_MATCHES) {
+        Mode::ListMatches
+    } else {
+        Mode::Run
     };
 
-    let config = tabletdrivercleanup::parse_to_config(modules, matches);
+    let nothing_found_code = matches.get_flag(constants::NOTHING_FOUND_CODE);
+    let config = match tabletdrivercleanup::parse_to_config(modules, matches) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(err.exit_code());
+        }
+    };
 
-    match mode {
-        Mode::Run => tabletdrivercleanup::run(config).await,
+    let result = match mode {
+        Mode::Run => match tabletdrivercleanup::run(config).await {
+            Ok(summary) if nothing_found_code && summary.matched_count == Some(0) => {
+                std::process::exit(constants::EXIT_NOTHING_FOUND)
+            }
+            result => result.map(|_| ()),
+        },
         Mode::Dump => tabletdrivercleanup::dump(config).await,
+        Mode::ListMatches => tabletdrivercleanup::list_matches(config).await,
     };
+
+    if let Err(err) = result {
+        std::process::exit(err.exit_code());
+    }
+}
+
+fn list_modules(modules: &[Box<dyn Module>]) {
+    for module in modules {
+        println!(
+            "{} ({}): {}",
+            module.cli_name(),
+            module.name(),
+            module.help()
+        );
+    }
 }
 
 fn add_modules_to_command(mut command: Command, modules: &[Box<dyn Module>]) -> Command {
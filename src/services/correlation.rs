@@ -0,0 +1,70 @@
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::services::interest::is_of_interest_iter;
+use crate::services::windows::{Device, Driver, DriverPackage};
+
+#[derive(Debug, Error)]
+#[error("Failed to correlate devices, drivers and driver packages")]
+pub struct CorrelationError;
+
+/// A driver together with the devices using it and the driver packages likely to have installed it.
+#[derive(Serialize)]
+pub struct Correlation<'a> {
+    driver: &'a Driver,
+    devices: Vec<&'a Device>,
+    driver_packages: Vec<&'a DriverPackage>,
+}
+
+/// Groups devices and driver packages under the drivers they relate to. Only drivers that are
+/// of interest, or that are used by a device of interest, are included.
+///
+/// Devices are linked by INF name, while driver packages are linked when their publisher
+/// contains the provider of the driver.
+pub fn correlate<'a>(
+    devices: &'a [Device],
+    drivers: &'a [Driver],
+    driver_packages: &'a [DriverPackage],
+) -> Vec<Correlation<'a>> {
+    drivers
+        .iter()
+        .filter_map(|driver| {
+            let devices: Vec<&Device> = devices
+                .iter()
+                .filter(|device| {
+                    device.inf_name().map_or(false, |inf_name| {
+                        inf_name.eq_ignore_ascii_case(driver.inf_name())
+                    })
+                })
+                .collect();
+
+            let driver_strings = [driver.provider(), driver.inf_original_name()];
+            let device_strings = devices
+                .iter()
+                .flat_map(|device| [device.description(), device.manufacturer()]);
+            if !is_of_interest_iter(driver_strings.into_iter().chain(device_strings).flatten()) {
+                return None;
+            }
+
+            let provider = driver
+                .provider()
+                .filter(|provider| !provider.is_empty())
+                .map(str::to_lowercase);
+            let driver_packages = driver_packages
+                .iter()
+                .filter(|package| match (&provider, package.publisher()) {
+                    (Some(provider), Some(publisher)) => {
+                        publisher.to_lowercase().contains(provider.as_str())
+                    }
+                    _ => false,
+                })
+                .collect();
+
+            Some(Correlation {
+                driver,
+                devices,
+                driver_packages,
+            })
+        })
+        .collect()
+}
@@ -1,16 +1,33 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
 use error_stack::{bail, report, IntoReport, Result, ResultExt};
+use futures::future::join_all;
 use include_dir::include_dir;
+use lazy_static::lazy_static;
 use log::{error, info, warn};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 use crate::{no_color, State};
 
+/// Environment variable naming a directory whose identifiers override all other sources.
+pub const CONFIG_DIR_ENV: &str = "TDC_CONFIG_DIR";
+
 static EMBEDDED_IDENTIFIERS: include_dir::Dir = include_dir!("$CARGO_MANIFEST_DIR/config");
 
+lazy_static! {
+    static ref CLIENT: Mutex<Option<reqwest::Client>> = Mutex::new(None);
+    /// Identifier files fetched ahead of time by [`prefetch_online`], taken out on first use.
+    static ref PREFETCHED: Mutex<HashMap<&'static str, Vec<u8>>> = Mutex::new(HashMap::new());
+}
+
 pub enum Source {
     Embed(&'static [u8]),
     Local(Vec<u8>),
@@ -27,6 +44,106 @@ impl Source {
     }
 }
 
+#[derive(Debug, Default, Clone)]
+pub struct IdentifierMetadata {
+    pub version: Option<u32>,
+    pub updated_at: Option<String>,
+    /// Lets maintainers turn a module off without a release. Modules run unless `false`.
+    pub enabled: Option<bool>,
+}
+
+impl std::fmt::Display for IdentifierMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.version, &self.updated_at) {
+            (Some(version), Some(updated_at)) => write!(f, "v{} ({})", version, updated_at),
+            (Some(version), None) => write!(f, "v{}", version),
+            (None, Some(updated_at)) => write!(f, "unversioned ({})", updated_at),
+            (None, None) => write!(f, "unversioned"),
+        }
+    }
+}
+
+/// An identifier file, either a bare array of identifiers or an object with metadata.
+pub struct IdentifierFile<T> {
+    pub metadata: IdentifierMetadata,
+    pub identifiers: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct VersionedIdentifierFile<T> {
+    version: Option<u32>,
+    updated_at: Option<String>,
+    enabled: Option<bool>,
+    identifiers: T,
+}
+
+impl<T> VersionedIdentifierFile<T> {
+    fn metadata(&self) -> IdentifierMetadata {
+        IdentifierMetadata {
+            version: self.version,
+            updated_at: self.updated_at.clone(),
+            enabled: self.enabled,
+        }
+    }
+}
+
+fn is_bare(content: &[u8]) -> bool {
+    content
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .map_or(true, |&b| b == b'[')
+}
+
+pub fn parse<T: DeserializeOwned>(content: &[u8]) -> serde_json::Result<IdentifierFile<T>> {
+    if is_bare(content) {
+        return Ok(IdentifierFile {
+            metadata: IdentifierMetadata::default(),
+            identifiers: serde_json::from_slice(content)?,
+        });
+    }
+
+    let file: VersionedIdentifierFile<Vec<T>> = serde_json::from_slice(content)?;
+    Ok(IdentifierFile {
+        metadata: file.metadata(),
+        identifiers: file.identifiers,
+    })
+}
+
+pub fn parse_metadata(content: &[u8]) -> IdentifierMetadata {
+    if is_bare(content) {
+        return IdentifierMetadata::default();
+    }
+
+    serde_json::from_slice::<VersionedIdentifierFile<IgnoredAny>>(content)
+        .map(|file| file.metadata())
+        .unwrap_or_default()
+}
+
+/// Hex-encoded SHA-256 of an identifier file, to tell exactly which revision of it a run used
+/// regardless of where it was loaded from.
+pub fn digest(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Names of the embedded identifier files, which are also the names used online and in the
+/// cache.
+pub fn embedded_names() -> impl Iterator<Item = &'static str> {
+    EMBEDDED_IDENTIFIERS
+        .files()
+        .filter_map(|file| file.path().to_str())
+}
+
+/// Gets the highest version among the embedded identifier files.
+pub fn embedded_version() -> Option<u32> {
+    EMBEDDED_IDENTIFIERS
+        .files()
+        .filter_map(|file| parse_metadata(file.contents()).version)
+        .max()
+}
+
 #[derive(Debug, Error)]
 pub enum RetrievalErr {
     #[error("Retrieval Error: Getting a resource from '{0}' is not allowed")]
@@ -53,11 +170,43 @@ impl std::fmt::Display for RetrievalMethod {
 }
 
 pub async fn get_resource(identifier: &'static str, state: &State) -> Result<Source, RetrievalErr> {
+    if let Some(resource) = get_resource_override(identifier) {
+        return Ok(resource);
+    }
+
+    // a refreshed identifier skips the cache, falling back to it only if the fetch fails
+    let refresh = state
+        .refresh_identifiers
+        .iter()
+        .any(|name| name == identifier);
+    if refresh {
+        match get_resource_online(identifier, state).await {
+            Ok(resource) => {
+                no_color(|| info!("Refreshed resource '{}' online", identifier));
+                return Ok(resource);
+            }
+            Err(err) => match err.current_context() {
+                RetrievalErr::Disallowed(_) => {}
+                _ => no_color(|| warn!("{:?}", err)),
+            },
+        }
+    }
+
     let resource = get_resource_offline(identifier, state);
 
     match resource {
         Ok(resource) => {
-            no_color(|| info!("Got resource '{} offline'", identifier));
+            let config_dir = state.current_path.join("config");
+            no_color(|| {
+                info!(
+                    "Got resource '{}' offline from '{}'",
+                    identifier,
+                    config_dir.display()
+                )
+            });
+            if let Some(newer) = get_newer_resource_online(identifier, state, &resource).await {
+                return Ok(newer);
+            }
             return Ok(resource);
         }
         Err(err) => match err.current_context() {
@@ -81,7 +230,7 @@ pub async fn get_resource(identifier: &'static str, state: &State) -> Result<Sou
         },
     }
 
-    let resource = get_resource_embed(identifier, state)
+    let resource = get_resource_embed(identifier)
         .attach_printable_lazy(|| format!("cannot get '{}' embedded", identifier));
 
     match resource {
@@ -96,6 +245,28 @@ pub async fn get_resource(identifier: &'static str, state: &State) -> Result<Sou
     }
 }
 
+/// Reads an identifier from the directory named by [`CONFIG_DIR_ENV`], which takes
+/// precedence over the cache, the repository and the embedded identifiers.
+fn get_resource_override(identifier: &'static str) -> Option<Source> {
+    let path = get_override_path(identifier)?;
+
+    match std::fs::read(&path) {
+        Ok(content) => {
+            no_color(|| info!("Got resource '{}' from '{}'", identifier, path.display()));
+            Some(Source::Local(content))
+        }
+        Err(err) => {
+            info!("'{}' is not overridden: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+fn get_override_path(identifier: &'static str) -> Option<PathBuf> {
+    let dir = std::env::var_os(CONFIG_DIR_ENV)?;
+    Some(Path::new(&dir).join(identifier))
+}
+
 fn get_resource_offline(identifier: &'static str, state: &State) -> Result<Source, RetrievalErr> {
     if !state.use_cache {
         bail!(RetrievalErr::Disallowed("offline"));
@@ -117,6 +288,23 @@ fn get_resource_offline(identifier: &'static str, state: &State) -> Result<Sourc
         .change_context(RetrievalErr::Err(identifier, RetrievalMethod::Offline))
         .attach_printable_lazy(|| format!("cannot open a handle to {:?}", path))?;
 
+    if let Some(days) = state.keep_cache_days {
+        let max_age = Duration::from_secs(days.saturating_mul(24 * 60 * 60));
+        let age = file
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok());
+
+        if age.map_or(false, |age| age > max_age) {
+            return Err(report!(RetrievalErr::Err(
+                identifier,
+                RetrievalMethod::Offline
+            )))
+            .attach_printable_lazy(|| format!("{:?} is older than {} days", path, days));
+        }
+    }
+
     let mut content = Vec::new();
     file.read_to_end(&mut content)
         .into_report()
@@ -126,23 +314,140 @@ fn get_resource_offline(identifier: &'static str, state: &State) -> Result<Sourc
     Ok(Source::Local(content))
 }
 
+async fn get_newer_resource_online(
+    identifier: &'static str,
+    state: &State,
+    cached: &Source,
+) -> Option<Source> {
+    let cached_version = parse_metadata(cached.get_content()).version;
+
+    let content = match fetch_resource_online(identifier, state).await {
+        Ok(content) => content,
+        Err(err) => {
+            if !matches!(err.current_context(), RetrievalErr::Disallowed(_)) {
+                no_color(|| warn!("{:?}", err));
+            }
+            return None;
+        }
+    };
+
+    let remote_version = parse_metadata(&content).version?;
+    if !is_newer(remote_version, cached_version) {
+        return None;
+    }
+
+    no_color(|| {
+        info!(
+            "Remote '{}' (v{}) is newer than cached ({})",
+            identifier,
+            remote_version,
+            cached_version.map_or("unversioned".to_string(), |v| format!("v{}", v))
+        )
+    });
+
+    if let Err(err) = write_cache(identifier, state, &content) {
+        no_color(|| warn!("{:?}", err));
+    }
+
+    Some(Source::Remote(content))
+}
+
+/// Whether a remote identifier file is newer than the cached one. Any versioned file is newer
+/// than an unversioned one, which predates versioning.
+fn is_newer(remote_version: u32, cached_version: Option<u32>) -> bool {
+    cached_version.map_or(true, |cached_version| remote_version > cached_version)
+}
+
 async fn get_resource_online(
     identifier: &'static str,
     state: &State,
 ) -> Result<Source, RetrievalErr> {
+    let content = fetch_resource_online(identifier, state).await?;
+
+    if state.use_cache {
+        write_cache(identifier, state, &content)?;
+    }
+
+    Ok(Source::Remote(content))
+}
+
+/// `HTTP_PROXY` and `HTTPS_PROXY` are respected by reqwest unless a proxy is set explicitly.
+///
+/// The client is built once and shared, so connections are reused across requests.
+pub fn build_client(state: &State) -> reqwest::Result<reqwest::Client> {
+    let mut client = CLIENT.lock().unwrap();
+    if let Some(client) = client.as_ref() {
+        return Ok(client.clone());
+    }
+
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = &state.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+
+    let built = builder.build()?;
+    *client = Some(built.clone());
+    Ok(built)
+}
+
+/// Fetches the given identifier files online at once, so modules loading them one after
+/// another do not each wait on their own request. Only files that [`get_resource`] will fetch
+/// online are prefetched. Files that fail here are fetched again, and reported, when their
+/// module asks for them.
+pub async fn prefetch_online(state: &State, identifiers: impl Iterator<Item = &'static str>) {
+    let identifiers = identifiers.filter(|&identifier| will_fetch_online(identifier, state));
+    let fetches = identifiers.map(|identifier| async move {
+        (identifier, fetch_resource_online(identifier, state).await)
+    });
+
+    for (identifier, result) in join_all(fetches).await {
+        match result {
+            Ok(content) => {
+                PREFETCHED.lock().unwrap().insert(identifier, content);
+            }
+            Err(err) => info!("Prefetching '{}' failed: {:?}", identifier, err),
+        }
+    }
+}
+
+/// Whether [`get_resource`] goes online for the identifier, either because it is not cached
+/// or to check for a newer one than the cache. Overridden identifiers never are.
+fn will_fetch_online(identifier: &'static str, state: &State) -> bool {
+    state.allow_updates && get_override_path(identifier).map_or(true, |path| !path.is_file())
+}
+
+async fn fetch_resource_online(
+    identifier: &'static str,
+    state: &State,
+) -> Result<Vec<u8>, RetrievalErr> {
     if !state.allow_updates {
         bail!(RetrievalErr::Disallowed("online"))
     }
 
+    if let Some(content) = PREFETCHED.lock().unwrap().remove(identifier) {
+        return Ok(content);
+    }
+
     let base_url = "https://raw.githubusercontent.com/X9VoiD/TabletDriverCleanup";
     let git_ref = "v4.x";
     let url = format!("{base_url}/{git_ref}/config/{identifier}");
 
-    let response = reqwest::get(&url)
+    let client = build_client(state)
+        .into_report()
+        .change_context(RetrievalErr::Err(identifier, RetrievalMethod::Online))
+        .attach_printable("cannot create http client")?;
+
+    let response = client
+        .get(&url)
+        .send()
         .await
         .into_report()
         .change_context(RetrievalErr::Err(identifier, RetrievalMethod::Online))
-        .attach_printable_lazy(|| format!("cannot get resource from {url}"))?;
+        .attach_printable_lazy(|| format!("cannot get resource from {url}"))
+        .attach_printable_lazy(|| match &state.proxy {
+            Some(proxy) => format!("using proxy {proxy}"),
+            None => "using system proxy settings".to_string(),
+        })?;
 
     if !response.status().is_success() {
         return Err(report!(RetrievalErr::Err(
@@ -165,21 +470,27 @@ async fn get_resource_online(
         .attach_printable_lazy(|| format!("cannot get resource content from {url}"))?
         .to_vec();
 
-    if state.use_cache {
-        let path = Path::new(&state.current_path)
-            .join("config")
-            .join(identifier);
-        let mut file = File::create(&path)
-            .into_report()
-            .change_context(RetrievalErr::Err(identifier, RetrievalMethod::Online))
-            .attach_printable_lazy(|| format!("cannot create a handle to {:?}", path))?;
-        file.write_all(&content).unwrap();
-    }
+    Ok(content)
+}
 
-    Ok(Source::Remote(content))
+fn write_cache(
+    identifier: &'static str,
+    state: &State,
+    content: &[u8],
+) -> Result<(), RetrievalErr> {
+    let path = Path::new(&state.current_path)
+        .join("config")
+        .join(identifier);
+    let mut file = File::create(&path)
+        .into_report()
+        .change_context(RetrievalErr::Err(identifier, RetrievalMethod::Online))
+        .attach_printable_lazy(|| format!("cannot create a handle to {:?}", path))?;
+    file.write_all(content).unwrap();
+
+    Ok(())
 }
 
-fn get_resource_embed(identifier: &'static str, _state: &State) -> Result<Source, RetrievalErr> {
+pub fn get_resource_embed(identifier: &'static str) -> Result<Source, RetrievalErr> {
     Ok(Source::Embed(
         match EMBEDDED_IDENTIFIERS.get_file(identifier) {
             Some(file) => file.contents(),
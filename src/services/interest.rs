@@ -2,41 +2,68 @@ use std::collections::HashMap;
 
 use lazy_static::lazy_static;
 use regex::{Regex, RegexBuilder};
+use thiserror::Error;
+
+type InterestMap = HashMap<&'static str, Regex>;
 
 lazy_static! {
-    static ref INTEREST_CACHE: HashMap::<&'static str, Regex> = {
-        create_map(&[
-            r".*\(Interface[ _]\d+\)",
-            "10moon",
-            "Acepen",
-            "Artisul",
-            "Digitizer",
-            "EMR",
-            "filtr",
-            "Gaomon",
-            "Genius",
-            "Huion",
-            "Kenting",
-            "libwdi",
-            "Lifetec",
-            "Monoprice",
-            "Parblo",
-            "RobotPen",
-            "Tablet",
-            "UC[-| ]?Logic",
-            "UGEE",
-            "Veikk",
-            "ViewSonic",
-            r"v\w*hid",
-            "Wacom",
-            "WinUSB",
-            "XenceLabs",
-            "XENX",
-            "XP[-| ]?Pen",
-        ])
+    static ref INTEREST_CACHE: Result<InterestMap, InterestError> = {
+        create_map(
+            &[
+                r".*\(Interface[ _]\d+\)",
+                "10moon",
+                "Acepen",
+                "Artisul",
+                "Digitizer",
+                "filtr",
+                "Gaomon",
+                "Genius",
+                "Huion",
+                "Kenting",
+                "libwdi",
+                "Lifetec",
+                "Monoprice",
+                "Parblo",
+                "RobotPen",
+                "Tablet",
+                "UC[-| ]?Logic",
+                "UGEE",
+                "Veikk",
+                "ViewSonic",
+                r"v\w*hid",
+                "Wacom",
+                "WinUSB",
+                "XenceLabs",
+                "XP[-| ]?Pen",
+            ],
+            // short tokens that are easily found inside unrelated words
+            &["EMR", "XENX"],
+        )
     };
-    static ref COUNTER_INTEREST_CACHE: HashMap::<&'static str, Regex> =
-        create_map(&["android", "logitech", " PS3 "]);
+    static ref COUNTER_INTEREST_CACHE: Result<InterestMap, InterestError> =
+        create_map(&["android", "logitech", " PS3 "], &[]);
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid interest pattern '{term}': {reason}")]
+pub struct InterestError {
+    term: &'static str,
+    reason: String,
+}
+
+/// Compiles the interest patterns, naming the first one that is invalid.
+pub fn verify() -> Result<(), InterestError> {
+    interests()?;
+    counter_interests()?;
+    Ok(())
+}
+
+fn interests() -> Result<&'static InterestMap, InterestError> {
+    INTEREST_CACHE.as_ref().map_err(Clone::clone)
+}
+
+fn counter_interests() -> Result<&'static InterestMap, InterestError> {
+    COUNTER_INTEREST_CACHE.as_ref().map_err(Clone::clone)
 }
 
 pub fn is_of_interest(string: Option<&str>) -> bool {
@@ -45,9 +72,15 @@ pub fn is_of_interest(string: Option<&str>) -> bool {
         None => return false,
     };
 
-    for regex in INTEREST_CACHE.values() {
+    // invalid patterns are reported on startup by `verify`
+    let (interests, counter_interests) = match (interests(), counter_interests()) {
+        (Ok(interests), Ok(counter_interests)) => (interests, counter_interests),
+        _ => return false,
+    };
+
+    for regex in interests.values() {
         if regex.is_match(string) {
-            for regex in COUNTER_INTEREST_CACHE.values() {
+            for regex in counter_interests.values() {
                 if regex.is_match(string) {
                     return false;
                 }
@@ -59,25 +92,78 @@ pub fn is_of_interest(string: Option<&str>) -> bool {
     false
 }
 
+pub fn get_interest_matches(string: &str) -> (Vec<&'static str>, Vec<&'static str>) {
+    let find_matches = |map: &InterestMap| {
+        let mut matches: Vec<&'static str> = map
+            .iter()
+            .filter(|(_, regex)| regex.is_match(string))
+            .map(|(&interest, _)| interest)
+            .collect();
+        matches.sort_unstable();
+        matches
+    };
+
+    (
+        interests().map(find_matches).unwrap_or_default(),
+        counter_interests().map(find_matches).unwrap_or_default(),
+    )
+}
+
 pub fn is_of_interest_iter<'a>(mut strings: impl Iterator<Item = &'a str>) -> bool {
     strings.any(|string| is_of_interest(Some(string)))
 }
 
-fn create_map(interests: &[&'static str]) -> HashMap<&'static str, Regex> {
+/// Creates the interest map. `words` only match when not surrounded by other letters,
+/// so "EMR" matches "EMR_Pen" but not "Remember".
+fn create_map(
+    interests: &[&'static str],
+    words: &[&'static str],
+) -> Result<InterestMap, InterestError> {
     let mut map = HashMap::new();
     for interest in interests {
-        add_interest(&mut map, interest);
+        add_interest(&mut map, interest, interest)?;
     }
 
-    map
+    for word in words {
+        let pattern = format!("(?:^|[^a-z]){}(?:[^a-z]|$)", word);
+        add_interest(&mut map, word, &pattern)?;
+    }
+
+    Ok(map)
+}
+
+fn add_interest(
+    map: &mut InterestMap,
+    string: &'static str,
+    pattern: &str,
+) -> Result<(), InterestError> {
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|err| InterestError {
+            term: string,
+            reason: err.to_string(),
+        })?;
+
+    map.insert(string, regex);
+    Ok(())
 }
 
-fn add_interest(map: &mut HashMap<&'static str, Regex>, string: &'static str) {
-    map.insert(
-        string,
-        RegexBuilder::new(string)
-            .case_insensitive(true)
-            .build()
-            .unwrap(),
-    );
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_matches_on_its_own() {
+        assert!(is_of_interest(Some("EMR_Pen")));
+        assert!(is_of_interest(Some("HID EMR Device")));
+        assert_eq!(get_interest_matches("EMR_Pen").0, ["EMR"]);
+    }
+
+    #[test]
+    fn word_does_not_match_inside_other_words() {
+        assert!(!is_of_interest(Some("REMEMBER")));
+        assert!(!is_of_interest(Some("Remember")));
+        assert!(get_interest_matches("Remember").0.is_empty());
+    }
 }
@@ -0,0 +1,65 @@
+use std::fs::File;
+use std::path::Path;
+
+use error_stack::{IntoReport, Result, ResultExt};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+const LAST_RUN_FILE: &str = "last-run.json";
+
+/// The outcome of every uninstall attempted during a run.
+#[derive(Serialize, Deserialize, Default)]
+pub struct LastRun {
+    outcomes: Vec<Outcome>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Outcome {
+    pub module: String,
+    pub object: String,
+    pub succeeded: bool,
+}
+
+impl LastRun {
+    pub fn new(outcomes: Vec<Outcome>) -> Self {
+        Self { outcomes }
+    }
+
+    /// Loads the previous run from `dir`, treating a missing or corrupt file as no previous run.
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(LAST_RUN_FILE);
+        let Ok(content) = std::fs::read(&path) else {
+            return Self::default();
+        };
+
+        match serde_json::from_slice(&content) {
+            Ok(last_run) => last_run,
+            Err(err) => {
+                warn!("Ignoring corrupt '{}': {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), std::io::Error> {
+        let path = dir.join(LAST_RUN_FILE);
+        let file = File::create(&path)
+            .into_report()
+            .attach_printable_lazy(|| format!("cannot create file '{}'", path.display()))?;
+
+        serde_json::to_writer_pretty(file, self)
+            .map_err(std::io::Error::from)
+            .into_report()
+            .attach_printable_lazy(|| format!("failed to write '{}'", path.display()))
+    }
+
+    pub fn has_failures(&self) -> bool {
+        self.outcomes.iter().any(|outcome| !outcome.succeeded)
+    }
+
+    pub fn has_failed(&self, module: &str, object: &str) -> bool {
+        self.outcomes.iter().any(|outcome| {
+            !outcome.succeeded && outcome.module == module && outcome.object == object
+        })
+    }
+}
@@ -1,5 +1,9 @@
+pub mod correlation;
 pub mod identifiers;
 pub mod interest;
+pub mod last_run;
 pub mod regex_cache;
+pub mod release;
 pub mod terminal;
+pub mod version;
 pub mod windows;
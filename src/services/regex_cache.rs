@@ -4,11 +4,40 @@ use std::sync::Mutex;
 
 use lazy_static::lazy_static;
 use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
 
 lazy_static! {
     static ref REGEX_CACHE: Mutex<HashMap<String, Regex>> = Mutex::new(HashMap::new());
 }
 
+/// How identifier patterns are interpreted.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// Patterns are regular expressions, matching anywhere in the input.
+    #[default]
+    Regex,
+    /// Patterns are globs where `*` matches any run of characters and `?` matches one,
+    /// matching the whole input.
+    Glob,
+}
+
+pub fn cached_match_with(input: Option<&str>, pattern: Option<&str>, mode: MatchMode) -> bool {
+    match mode {
+        MatchMode::Regex => cached_match(input, pattern),
+        MatchMode::Glob => cached_match(input, pattern.map(glob_to_regex).as_deref()),
+    }
+}
+
+/// Checks that `pattern` compiles the same way it would when matched.
+pub fn validate(pattern: &str, mode: MatchMode) -> Result<(), regex::Error> {
+    match mode {
+        MatchMode::Regex => try_build_regex(pattern),
+        MatchMode::Glob => try_build_regex(&glob_to_regex(pattern)),
+    }
+    .map(|_| ())
+}
+
 pub fn cached_match(input: Option<&str>, regex_pattern: Option<&str>) -> bool {
     let regex_pattern = match regex_pattern {
         Some(regex_pattern) => regex_pattern,
@@ -36,8 +65,26 @@ pub fn cached_match(input: Option<&str>, regex_pattern: Option<&str>) -> bool {
 }
 
 fn build_regex(regex: &str) -> Regex {
-    RegexBuilder::new(regex)
-        .case_insensitive(true)
-        .build()
-        .unwrap()
+    try_build_regex(regex).unwrap()
+}
+
+fn try_build_regex(regex: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(regex).case_insensitive(true).build()
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::with_capacity(glob.len() + 2);
+    regex.push('^');
+
+    let mut buffer = [0u8; 4];
+    for c in glob.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut buffer))),
+        }
+    }
+
+    regex.push('$');
+    regex
 }
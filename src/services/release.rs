@@ -0,0 +1,60 @@
+use error_stack::{IntoReport, Result, ResultExt};
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::constants;
+use crate::services::identifiers::build_client;
+use crate::services::version::Version;
+use crate::State;
+
+const LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/X9VoiD/TabletDriverCleanup/releases/latest";
+
+#[derive(Debug, Error)]
+#[error("Failed to check for a newer release")]
+pub struct ReleaseCheckError {}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+}
+
+/// Returns the tag of the latest release if it is newer than the running version.
+pub async fn get_newer_release(state: &State) -> Result<Option<String>, ReleaseCheckError> {
+    let release = fetch_latest_release(state).await?;
+
+    let is_newer = match (
+        Version::parse(&release.tag_name),
+        Version::parse(env!("CARGO_PKG_VERSION")),
+    ) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    };
+
+    Ok(is_newer.then_some(release.tag_name))
+}
+
+async fn fetch_latest_release(state: &State) -> Result<Release, ReleaseCheckError> {
+    let response = build_client(state)
+        .into_report()
+        .change_context(ReleaseCheckError {})?
+        .get(LATEST_RELEASE_URL)
+        .header(reqwest::header::USER_AGENT, constants::CLI_NAME)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .into_report()
+        .change_context(ReleaseCheckError {})
+        .attach_printable_lazy(|| format!("cannot get {}", LATEST_RELEASE_URL))?;
+
+    let content = response
+        .bytes()
+        .await
+        .into_report()
+        .change_context(ReleaseCheckError {})?;
+
+    serde_json::from_slice(&content)
+        .into_report()
+        .change_context(ReleaseCheckError {})
+        .attach_printable("cannot parse release information")
+}
@@ -10,10 +10,14 @@ use thiserror::Error;
 use tokio::time::sleep;
 use tokio_util::sync::CancellationToken;
 
+use crate::constants;
+
 #[derive(PartialEq)]
 pub enum PromptResult {
     Yes,
     No,
+    /// Yes to this and everything after it.
+    All,
     Cancel,
 }
 
@@ -44,9 +48,22 @@ pub fn enter_temp_print() -> TempPrintGuard {
 }
 
 pub fn prompt_yes_no(message: &str) -> PromptResult {
+    prompt(message, false)
+}
+
+/// Like [`prompt_yes_no`], but also accepts 'a' for [`PromptResult::All`].
+pub fn prompt_yes_no_all(message: &str) -> PromptResult {
+    prompt(message, true)
+}
+
+fn prompt(message: &str, allow_all: bool) -> PromptResult {
+    let options = match allow_all {
+        true => "Y/n/a",
+        false => "Y/n",
+    };
     let get_key = || {
         temporary_print(|| {
-            print!("{} (Y/n) ", message);
+            print!("{} ({}) ", message, options);
             std::io::stdout().flush().unwrap();
             read_key().unwrap()
         })
@@ -56,12 +73,85 @@ pub fn prompt_yes_no(message: &str) -> PromptResult {
         match get_key().code {
             KeyCode::Char('y') | KeyCode::Enter => break PromptResult::Yes,
             KeyCode::Char('n') => break PromptResult::No,
+            KeyCode::Char('a') if allow_all => break PromptResult::All,
             KeyCode::Esc => break PromptResult::Cancel,
             _ => {}
         }
     }
 }
 
+/// Prompts for the 1-based indices to deselect out of `count` items.
+///
+/// Returns the selection state of each item, or `None` if the user aborts.
+pub fn prompt_deselect(count: usize) -> Option<Vec<bool>> {
+    loop {
+        print!(
+            "\nEnter the numbers to skip (e.g. '1 3'), leave empty to proceed, or 'q' to abort: "
+        );
+        std::io::stdout().flush().unwrap();
+
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input).unwrap();
+        let input = input.trim();
+
+        if input.eq_ignore_ascii_case("q") {
+            break None;
+        }
+
+        let indices: Option<Vec<usize>> = input
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<usize>().ok().filter(|&i| i >= 1 && i <= count))
+            .collect();
+
+        match indices {
+            Some(indices) => {
+                let mut selection = vec![true; count];
+                for i in indices {
+                    selection[i - 1] = false;
+                }
+                break Some(selection);
+            }
+            None => println!("Invalid selection, please try again."),
+        }
+    }
+}
+
+/// Shows a list of items, all checked at first, to toggle with the arrow keys and space.
+///
+/// Returns the selection state of each item, or `None` if the user aborts.
+pub fn prompt_checklist(message: &str, items: &[&str]) -> Option<Vec<bool>> {
+    let mut selection = vec![true; items.len()];
+    if items.is_empty() {
+        return Some(selection);
+    }
+
+    let mut current = 0;
+    loop {
+        let key = temporary_print(|| {
+            println!(
+                "{} (arrows to move, space to toggle, enter to proceed, esc to abort)",
+                message
+            );
+            for (i, item) in items.iter().enumerate() {
+                let pointer = if i == current { '>' } else { ' ' };
+                let check = if selection[i] { 'x' } else { ' ' };
+                println!("{} [{}] {}", pointer, check, item);
+            }
+            read_key().unwrap()
+        });
+
+        match key.code {
+            KeyCode::Up => current = current.checked_sub(1).unwrap_or(items.len() - 1),
+            KeyCode::Down => current = (current + 1) % items.len(),
+            KeyCode::Char(' ') => selection[current] = !selection[current],
+            KeyCode::Enter => break Some(selection),
+            KeyCode::Esc => break None,
+            _ => {}
+        }
+    }
+}
+
 pub fn temporary_print<T>(action: impl FnOnce() -> T) -> T {
     let _guard = enter_temp_print();
     action()
@@ -93,7 +183,7 @@ pub async fn read_key_async(ct: Option<CancellationToken>) -> Result<WaitResult,
                     }
                 }
             } else {
-                sleep(Duration::from_millis(20)).await;
+                sleep(constants::POLL_INTERVAL).await;
             }
         }
     })
@@ -101,6 +191,28 @@ pub async fn read_key_async(ct: Option<CancellationToken>) -> Result<WaitResult,
     .unwrap()
 }
 
+/// Like [`read_key_async`], but gives up after `seconds` with [`WaitResult::Cancelled`],
+/// showing a countdown meanwhile.
+pub async fn read_key_with_countdown(seconds: u64) -> Result<WaitResult, ReadKeyError> {
+    let ct = CancellationToken::new();
+    let countdown = tokio::spawn({
+        let ct = ct.clone();
+        async move {
+            for remaining in (1..=seconds).rev() {
+                print!("\rContinuing in {}s... ", remaining);
+                _ = stdout().flush();
+                sleep(Duration::from_secs(1)).await;
+            }
+            ct.cancel();
+        }
+    });
+
+    let result = read_key_async(Some(ct)).await;
+    countdown.abort();
+    println!();
+    result
+}
+
 #[derive(Debug)]
 pub enum WaitResult {
     Key(KeyEvent),
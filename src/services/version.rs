@@ -0,0 +1,95 @@
+use std::cmp::Ordering;
+
+/// A dotted numeric version such as `4.0.3` or `6.1.7600.16385`.
+/// Missing components compare as zero, so `1.2` equals `1.2.0`.
+#[derive(Debug, Clone)]
+pub struct Version(Vec<u32>);
+
+impl Version {
+    /// Parses a version, ignoring a leading `v`.
+    pub fn parse(version: &str) -> Option<Self> {
+        version
+            .trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map(|part| part.parse().ok())
+            .collect::<Option<Vec<u32>>>()
+            .map(Version)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        (0..len)
+            .map(|i| {
+                let left = self.0.get(i).copied().unwrap_or(0);
+                let right = other.0.get(i).copied().unwrap_or(0);
+                left.cmp(&right)
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+/// Comma-separated comparisons that must all hold, such as `>=5.0, <6.3`.
+/// A version without an operator must match exactly.
+#[derive(Debug, Clone)]
+pub struct VersionRange(Vec<(Comparison, Version)>);
+
+impl VersionRange {
+    pub fn parse(range: &str) -> Option<Self> {
+        range
+            .split(',')
+            .map(|comparator| {
+                let comparator = comparator.trim();
+                let (comparison, version) = [
+                    ("<=", Comparison::LessOrEqual),
+                    (">=", Comparison::GreaterOrEqual),
+                    ("<", Comparison::Less),
+                    (">", Comparison::Greater),
+                    ("=", Comparison::Equal),
+                ]
+                .into_iter()
+                .find_map(|(op, comparison)| Some((comparison, comparator.strip_prefix(op)?)))
+                .unwrap_or((Comparison::Equal, comparator));
+
+                Some((comparison, Version::parse(version)?))
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(VersionRange)
+    }
+
+    pub fn contains(&self, version: &Version) -> bool {
+        self.0.iter().all(|(comparison, bound)| match comparison {
+            Comparison::Less => version < bound,
+            Comparison::LessOrEqual => version <= bound,
+            Comparison::Greater => version > bound,
+            Comparison::GreaterOrEqual => version >= bound,
+            Comparison::Equal => version == bound,
+        })
+    }
+}
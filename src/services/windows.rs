@@ -1,32 +1,64 @@
 use core::fmt::Debug;
 use core::result::Result as CResult;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
 use std::ffi::{c_void, OsStr, OsString};
 use std::fmt;
-use std::path::Path;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use error_stack::{bail, IntoReport, Result, ResultExt};
+use chrono::NaiveDate;
+use error_stack::{bail, report, IntoReport, Result, ResultExt};
 use lazy_static::lazy_static;
+use log::{debug, warn};
 use regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
-use windows::core::{HRESULT, HSTRING};
+use windows::core::{HRESULT, HSTRING, PCWSTR, PWSTR};
 use windows::Win32::Devices::DeviceAndDriverInstallation::*;
 use windows::Win32::Devices::Properties::*;
 use windows::Win32::Foundation::*;
 use windows::Win32::Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY};
+use windows::Win32::Storage::FileSystem::{
+    GetFileVersionInfoSizeW, GetFileVersionInfoW, MoveFileExW, VerQueryValueW,
+    MOVEFILE_DELAY_UNTIL_REBOOT,
+};
+use windows::Win32::System::Diagnostics::Debug::{
+    FormatMessageW, FORMAT_MESSAGE_FROM_SYSTEM, FORMAT_MESSAGE_IGNORE_INSERTS,
+};
+use windows::Win32::System::Environment::ExpandEnvironmentStringsW;
+use windows::Win32::System::SystemInformation::{
+    IMAGE_FILE_MACHINE, IMAGE_FILE_MACHINE_AMD64, IMAGE_FILE_MACHINE_ARM64,
+    IMAGE_FILE_MACHINE_ARMNT, IMAGE_FILE_MACHINE_I386, IMAGE_FILE_MACHINE_UNKNOWN,
+};
 use windows::Win32::System::Threading::{
-    GetCurrentProcess, OpenProcess, OpenProcessToken, WaitForSingleObject, PROCESS_SYNCHRONIZE,
+    GetCurrentProcess, GetProcessTimes, IsWow64Process2, OpenProcess, OpenProcessToken,
+    TerminateProcess, WaitForSingleObject, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SYNCHRONIZE,
+    PROCESS_TERMINATE,
+};
+use windows::Win32::UI::Shell::ShellExecuteW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    EnumWindows, GetWindowThreadProcessId, IsWindowVisible, SW_SHOWNORMAL,
 };
 use winreg::enums::*;
 use winreg::types::FromRegValue;
 use winreg::RegKey;
 
+use crate::constants;
+
+const SESSION_MANAGER_KEY: &str = "SYSTEM\\CurrentControlSet\\Control\\Session Manager";
+const CBS_REBOOT_PENDING_KEY: &str =
+    "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Component Based Servicing\\RebootPending";
 const X64_UNINSTALL_KEY: &str = "SOFTWARE\\Microsoft\\Windows\\CurrentVersion\\Uninstall";
 const X86_UNINSTALL_KEY: &str =
     "SOFTWARE\\Wow6432Node\\Microsoft\\Windows\\CurrentVersion\\Uninstall";
+const CURRENT_VERSION_KEY: &str = "SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion";
+const CLASS_KEY: &str = "SYSTEM\\CurrentControlSet\\Control\\Class";
+const SERVICES_KEY: &str = "SYSTEM\\CurrentControlSet\\Services";
+/// `SERVICE_KERNEL_DRIVER` in the `Type` value of a service.
+const SERVICE_KERNEL_DRIVER: u32 = 0x1;
 
 #[derive(Debug, Error)]
 enum FfiError {
@@ -44,16 +76,34 @@ pub enum EnumerationError {
     Driver,
     #[error("Failed to enumerate driver packages")]
     DriverPackage,
+    #[error("Failed to enumerate class filters")]
+    ClassFilter,
+    #[error("Failed to enumerate kernel drivers")]
+    KernelDriver,
+    #[error("Failed to enumerate the driver store")]
+    DriverStore,
+    #[error("Failed to enumerate system files")]
+    SystemFile,
 }
 
 #[derive(Error, Debug)]
 pub enum WaitError {
     #[error("Timed out waiting for process")]
     Timeout,
+    #[error("Cancelled waiting for process")]
+    Cancelled,
     #[error("Failed to wait for process")]
     Failed,
 }
 
+#[derive(Debug, Error)]
+#[error("Failed to terminate process")]
+pub struct TerminateError {}
+
+#[derive(Debug, Error)]
+#[error("Failed to relaunch as administrator")]
+pub struct ElevationError {}
+
 struct Handle {
     handle: HANDLE,
 }
@@ -100,8 +150,10 @@ struct GenericGetError {
 #[derive(Serialize)]
 pub struct Device {
     is_generic: bool,
+    is_present: bool,
     instance_id: String,
     hardware_ids: Vec<String>,
+    compatible_ids: Vec<String>,
     friendly_name: Option<String>,
     description: Option<String>,
     manufacturer: Option<String>,
@@ -113,6 +165,30 @@ pub struct Device {
     inf_section: Option<String>,
     inf_provider: Option<String>,
     driver_store_location: Option<String>,
+    removal_policy: Option<RemovalPolicy>,
+    /// Instance id of the parent device, such as the USB composite device of an interface.
+    parent: Option<String>,
+    children: Vec<String>,
+}
+
+/// How Windows expects a device to be removed, from `DEVPKEY_Device_RemovalPolicy`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovalPolicy {
+    NoRemoval,
+    OrderlyRemoval,
+    SurpriseRemoval,
+}
+
+impl RemovalPolicy {
+    fn from_raw(policy: u32) -> Option<Self> {
+        match policy {
+            CM_REMOVAL_POLICY_EXPECT_NO_REMOVAL => Some(Self::NoRemoval),
+            CM_REMOVAL_POLICY_EXPECT_ORDERLY_REMOVAL => Some(Self::OrderlyRemoval),
+            CM_REMOVAL_POLICY_EXPECT_SURPRISE_REMOVAL => Some(Self::SurpriseRemoval),
+            _ => None,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -120,8 +196,10 @@ pub struct Device {
 impl Device {
     pub fn new(
         is_generic: bool,
+        is_present: bool,
         instance_id: String,
-        hardware_ids: Option<String>,
+        hardware_ids: Vec<String>,
+        compatible_ids: Vec<String>,
         friendly_name: Option<String>,
         description: Option<String>,
         manufacturer: Option<String>,
@@ -133,14 +211,16 @@ impl Device {
         inf_section: Option<String>,
         inf_provider: Option<String>,
         driver_store_location: Option<String>,
+        removal_policy: Option<RemovalPolicy>,
+        parent: Option<String>,
+        children: Vec<String>,
     ) -> Self {
         Self {
             is_generic,
+            is_present,
             instance_id,
-            hardware_ids: match hardware_ids {
-                Some(s) => s.split('\u{0}').map(|s| s.to_string()).collect(),
-                None => Vec::new(),
-            },
+            hardware_ids,
+            compatible_ids,
             friendly_name,
             description,
             manufacturer,
@@ -152,6 +232,9 @@ impl Device {
             inf_section,
             inf_provider,
             driver_store_location,
+            removal_policy,
+            parent,
+            children,
         }
     }
 
@@ -159,6 +242,12 @@ impl Device {
         self.is_generic
     }
 
+    /// Whether the device is currently connected. Devices that are not are often called
+    /// phantom or ghost devices.
+    pub fn is_present(&self) -> bool {
+        self.is_present
+    }
+
     pub fn instance_id(&self) -> &str {
         &self.instance_id
     }
@@ -167,10 +256,29 @@ impl Device {
         self.hardware_ids.as_slice()
     }
 
-    pub fn friendly_name(&self) -> Option<&str> {
-        match &self.friendly_name {
-            Some(_) => self.friendly_name.as_deref(),
-            None => self.description.as_deref(),
+    pub fn compatible_ids(&self) -> &[String] {
+        self.compatible_ids.as_slice()
+    }
+
+    /// Falls back to the description, then to the manufacturer and first hardware id
+    /// for devices that report neither.
+    pub fn friendly_name(&self) -> Option<Cow<'_, str>> {
+        fn non_empty(s: &Option<String>) -> Option<&str> {
+            s.as_deref().filter(|s| !s.is_empty())
+        }
+
+        let name = non_empty(&self.friendly_name).or_else(|| non_empty(&self.description));
+        if let Some(name) = name {
+            return Some(Cow::Borrowed(name));
+        }
+
+        let hardware_id = self.hardware_ids.first();
+        match (non_empty(&self.manufacturer), hardware_id) {
+            (Some(manufacturer), Some(hardware_id)) => {
+                Some(Cow::Owned(format!("{} {}", manufacturer, hardware_id)))
+            }
+            (None, Some(hardware_id)) => Some(Cow::Borrowed(hardware_id)),
+            _ => None,
         }
     }
 
@@ -213,6 +321,24 @@ impl Device {
     pub fn driver_store_location(&self) -> Option<&str> {
         self.driver_store_location.as_deref()
     }
+
+    pub fn removal_policy(&self) -> Option<RemovalPolicy> {
+        self.removal_policy
+    }
+
+    pub fn parent(&self) -> Option<&str> {
+        self.parent.as_deref()
+    }
+
+    pub fn children(&self) -> &[String] {
+        &self.children
+    }
+
+    /// Whether removing the device likely needs a reboot to complete. Windows does not expect
+    /// connected devices that are never removed, such as built-in digitizers, to go away live.
+    pub fn may_require_reboot(&self) -> bool {
+        self.is_present && self.removal_policy == Some(RemovalPolicy::NoRemoval)
+    }
 }
 
 impl fmt::Display for Device {
@@ -239,17 +365,22 @@ pub struct Driver {
     inf_original_name: Option<String>,
     driver_store_location: Option<String>,
     provider: Option<String>,
+    driver_version: Option<String>,
+    driver_date: Option<String>,
     class: Option<String>,
     class_guid: Uuid,
 }
 
 #[allow(dead_code)]
+#[allow(clippy::too_many_arguments)]
 impl Driver {
     pub fn new(
         inf_name: String,
         inf_original_name: Option<String>,
         driver_store_location: Option<String>,
         provider: Option<String>,
+        driver_version: Option<String>,
+        driver_date: Option<String>,
         class: Option<String>,
         class_guid: Uuid,
     ) -> Driver {
@@ -258,6 +389,8 @@ impl Driver {
             inf_original_name,
             driver_store_location,
             provider,
+            driver_version,
+            driver_date,
             class,
             class_guid,
         }
@@ -279,6 +412,17 @@ impl Driver {
         self.provider.as_deref()
     }
 
+    /// The version part of the INF's `DriverVer`.
+    pub fn driver_version(&self) -> Option<&str> {
+        self.driver_version.as_deref()
+    }
+
+    /// The date part of the INF's `DriverVer`, which should be `mm/dd/yyyy` but is not always
+    /// written that way.
+    pub fn driver_date(&self) -> Option<NaiveDate> {
+        parse_driver_date(self.driver_date.as_deref()?)
+    }
+
     pub fn class(&self) -> Option<&str> {
         self.class.as_deref()
     }
@@ -297,54 +441,208 @@ impl fmt::Display for Driver {
     }
 }
 
+/// Where a class filter sits relative to the function driver, named after its registry value.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterPosition {
+    UpperFilters,
+    LowerFilters,
+}
+
+impl FilterPosition {
+    fn value_name(&self) -> &'static str {
+        match self {
+            FilterPosition::UpperFilters => "UpperFilters",
+            FilterPosition::LowerFilters => "LowerFilters",
+        }
+    }
+}
+
+/// A filter driver listed in the `UpperFilters` or `LowerFilters` of a device setup class,
+/// loaded for every device of that class.
+#[derive(Serialize, Debug)]
+pub struct ClassFilter {
+    name: String,
+    position: FilterPosition,
+    class_name: Option<String>,
+    class_guid: Uuid,
+}
+
+impl ClassFilter {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn position(&self) -> FilterPosition {
+        self.position
+    }
+
+    pub fn class_name(&self) -> Option<&str> {
+        self.class_name.as_deref()
+    }
+
+    pub fn class_guid(&self) -> &Uuid {
+        &self.class_guid
+    }
+}
+
+impl fmt::Display for ClassFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.class_name {
+            Some(class_name) => write!(
+                f,
+                "{} ({} of {})",
+                self.name,
+                self.position.value_name(),
+                class_name
+            ),
+            None => write!(
+                f,
+                "{} ({} of {{{}}})",
+                self.name,
+                self.position.value_name(),
+                self.class_guid
+            ),
+        }
+    }
+}
+
+/// A kernel driver registered as a service, whether or not it was installed from an INF.
+#[derive(Serialize, Debug)]
+pub struct KernelDriver {
+    name: String,
+    display_name: Option<String>,
+    image_path: Option<String>,
+    start_type: Option<StartType>,
+}
+
+impl KernelDriver {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    pub fn image_path(&self) -> Option<&str> {
+        self.image_path.as_deref()
+    }
+
+    pub fn start_type(&self) -> Option<StartType> {
+        self.start_type
+    }
+}
+
+/// When a service is started, from the `Start` value of a service.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StartType {
+    Boot,
+    System,
+    Automatic,
+    Manual,
+    Disabled,
+}
+
+impl StartType {
+    fn from_value(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(StartType::Boot),
+            1 => Some(StartType::System),
+            2 => Some(StartType::Automatic),
+            3 => Some(StartType::Manual),
+            4 => Some(StartType::Disabled),
+            _ => None,
+        }
+    }
+}
+
+/// The registry hive a driver package is registered under.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageScope {
+    Machine,
+    User,
+}
+
+impl PackageScope {
+    pub fn root_key_name(&self) -> &'static str {
+        match self {
+            PackageScope::Machine => "HKEY_LOCAL_MACHINE",
+            PackageScope::User => "HKEY_CURRENT_USER",
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct DriverPackage {
     x86: bool,
+    scope: PackageScope,
     key_name: String,
     display_name: Option<String>,
     display_version: Option<String>,
     publisher: Option<String>,
     install_location: Option<String>,
     uninstall_string: Option<String>,
+    quiet_uninstall_string: Option<String>,
+    install_date: Option<String>,
+    system_component: bool,
 }
 
 #[allow(dead_code)]
+#[allow(clippy::too_many_arguments)]
 impl DriverPackage {
     pub fn new(
         x86: bool,
+        scope: PackageScope,
         key_name: String,
         display_name: Option<String>,
         display_version: Option<String>,
         publisher: Option<String>,
         install_location: Option<String>,
         uninstall_string: Option<String>,
+        quiet_uninstall_string: Option<String>,
+        install_date: Option<String>,
+        system_component: bool,
     ) -> Self {
         Self {
             x86,
+            scope,
             key_name,
             display_name,
             display_version,
             publisher,
             install_location,
             uninstall_string,
+            quiet_uninstall_string,
+            install_date,
+            system_component,
         }
     }
 
-    pub fn from(reg_key: &RegKey, name: String, x86: bool) -> Self {
+    pub fn from(reg_key: &RegKey, name: String, x86: bool, scope: PackageScope) -> Self {
         let display_name: Option<String> = Self::reg_get_value(reg_key, "DisplayName");
         let display_version: Option<String> = Self::reg_get_value(reg_key, "DisplayVersion");
         let publisher: Option<String> = Self::reg_get_value(reg_key, "Publisher");
         let install_location: Option<String> = Self::reg_get_value(reg_key, "InstallLocation");
         let uninstall_string: Option<String> = Self::reg_get_value(reg_key, "UninstallString");
+        let quiet_uninstall_string: Option<String> =
+            Self::reg_get_value(reg_key, "QuietUninstallString");
+        let install_date: Option<String> = Self::reg_get_value(reg_key, "InstallDate");
+        let system_component: Option<u32> = Self::reg_get_value(reg_key, "SystemComponent");
 
         Self::new(
             x86,
+            scope,
             name,
             display_name,
             display_version,
             publisher,
             install_location,
             uninstall_string,
+            quiet_uninstall_string,
+            install_date,
+            system_component == Some(1),
         )
     }
 
@@ -355,72 +653,807 @@ impl DriverPackage {
         }
     }
 
-    pub fn x86(&self) -> bool {
-        self.x86
+    pub fn x86(&self) -> bool {
+        self.x86
+    }
+
+    pub fn scope(&self) -> PackageScope {
+        self.scope
+    }
+
+    /// Opens the root of the hive this package is registered under.
+    pub fn root_key(&self) -> RegKey {
+        match self.scope {
+            PackageScope::Machine => RegKey::predef(HKEY_LOCAL_MACHINE),
+            PackageScope::User => RegKey::predef(HKEY_CURRENT_USER),
+        }
+    }
+
+    pub fn key_name(&self) -> &str {
+        &self.key_name
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    pub fn display_version(&self) -> Option<&str> {
+        self.display_version.as_deref()
+    }
+
+    pub fn publisher(&self) -> Option<&str> {
+        self.publisher.as_deref()
+    }
+
+    pub fn install_location(&self) -> Option<&str> {
+        self.install_location.as_deref()
+    }
+
+    pub fn uninstall_string(&self) -> Option<&str> {
+        self.uninstall_string.as_deref()
+    }
+
+    /// An uninstall command that runs without user interaction, if the package has one.
+    pub fn quiet_uninstall_string(&self) -> Option<&str> {
+        self.quiet_uninstall_string.as_deref()
+    }
+
+    /// The `InstallDate` of the package, which installers write as `YYYYMMDD`.
+    pub fn install_date(&self) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(self.install_date.as_deref()?.trim(), "%Y%m%d").ok()
+    }
+
+    /// Whether the package sets `SystemComponent`, which hides it from the list of installed
+    /// programs so it cannot be removed from there.
+    pub fn system_component(&self) -> bool {
+        self.system_component
+    }
+
+    /// Whether the uninstall key of this package still exists in the registry.
+    pub fn is_registered(&self) -> bool {
+        self.root_key().open_subkey(&self.key_name).is_ok()
+    }
+}
+
+impl fmt::Display for DriverPackage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.display_name() {
+            Some(display_name) => write!(f, "{}", display_name),
+            None => write!(f, "{}", self.key_name()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct OsInfo {
+    product_name: Option<String>,
+    edition: Option<String>,
+    display_version: Option<String>,
+    version: Option<String>,
+    build: Option<String>,
+    arch: Option<String>,
+    process_arch: Option<String>,
+    native_arch: Option<String>,
+    reboot_pending: bool,
+}
+
+#[allow(dead_code)]
+impl OsInfo {
+    pub fn product_name(&self) -> Option<&str> {
+        self.product_name.as_deref()
+    }
+
+    pub fn edition(&self) -> Option<&str> {
+        self.edition.as_deref()
+    }
+
+    pub fn display_version(&self) -> Option<&str> {
+        self.display_version.as_deref()
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub fn build(&self) -> Option<&str> {
+        self.build.as_deref()
+    }
+
+    pub fn arch(&self) -> Option<&str> {
+        self.arch.as_deref()
+    }
+
+    pub fn process_arch(&self) -> Option<&str> {
+        self.process_arch.as_deref()
+    }
+
+    pub fn native_arch(&self) -> Option<&str> {
+        self.native_arch.as_deref()
+    }
+
+    pub fn reboot_pending(&self) -> bool {
+        self.reboot_pending
+    }
+
+    /// Whether this program runs under emulation, e.g. the x64 build on ARM64 Windows.
+    pub fn is_emulated(&self) -> bool {
+        matches!((self.process_arch(), self.native_arch()), (Some(p), Some(n)) if p != n)
+    }
+}
+
+impl fmt::Display for OsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.product_name().unwrap_or("Windows"))?;
+
+        let details: Vec<String> = [
+            self.display_version().map(|v| v.to_string()),
+            self.build().map(|b| format!("build {}", b)),
+            self.arch().map(|a| a.to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Gets information about the running OS. Values that cannot be retrieved are left empty.
+pub fn get_os_info() -> OsInfo {
+    let key = match RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey(CURRENT_VERSION_KEY) {
+        Ok(key) => key,
+        Err(_) => {
+            let (process_arch, native_arch) = get_machine_archs().unzip();
+            return OsInfo {
+                arch: get_os_arch(),
+                process_arch: process_arch.map(str::to_string),
+                native_arch: native_arch.map(str::to_string),
+                reboot_pending: is_reboot_pending(),
+                ..Default::default()
+            };
+        }
+    };
+
+    let get_string = |name: &str| key.get_value::<String, _>(name).ok();
+    let get_u32 = |name: &str| key.get_value::<u32, _>(name).ok();
+
+    let build = get_string("CurrentBuild");
+    let build_number = build.as_deref().and_then(|b| b.parse::<u32>().ok());
+
+    // Windows 11 still reports itself as Windows 10 in ProductName
+    let product_name = get_string("ProductName").map(|name| match build_number {
+        Some(build) if build >= 22000 => name.replace("Windows 10", "Windows 11"),
+        _ => name,
+    });
+
+    let version = match (
+        get_u32("CurrentMajorVersionNumber"),
+        get_u32("CurrentMinorVersionNumber"),
+    ) {
+        (Some(major), Some(minor)) => Some(format!("{}.{}", major, minor)),
+        _ => get_string("CurrentVersion"),
+    };
+
+    let (process_arch, native_arch) = get_machine_archs().unzip();
+
+    let build = match (build, get_u32("UBR")) {
+        (Some(build), Some(ubr)) => Some(format!("{}.{}", build, ubr)),
+        (build, _) => build,
+    };
+
+    OsInfo {
+        product_name,
+        edition: get_string("EditionID"),
+        display_version: get_string("DisplayVersion").or_else(|| get_string("ReleaseId")),
+        version,
+        build,
+        arch: get_os_arch(),
+        process_arch: process_arch.map(str::to_string),
+        native_arch: native_arch.map(str::to_string),
+        reboot_pending: is_reboot_pending(),
+    }
+}
+
+/// Whether a previous install or uninstall is waiting for a reboot to complete.
+pub fn is_reboot_pending() -> bool {
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+
+    let pending_renames = hklm
+        .open_subkey(SESSION_MANAGER_KEY)
+        .and_then(|key| key.get_value::<Vec<String>, _>("PendingFileRenameOperations"))
+        .map_or(false, |renames| {
+            renames.iter().any(|rename| !rename.is_empty())
+        });
+
+    pending_renames || hklm.open_subkey(CBS_REBOOT_PENDING_KEY).is_ok()
+}
+
+fn get_os_arch() -> Option<String> {
+    // PROCESSOR_ARCHITEW6432 is only set for 32-bit processes running under WOW64
+    std::env::var("PROCESSOR_ARCHITEW6432")
+        .or_else(|_| std::env::var("PROCESSOR_ARCHITECTURE"))
+        .ok()
+}
+
+/// Gets the architecture this process runs as and the native architecture of the machine.
+fn get_machine_archs() -> Option<(&'static str, &'static str)> {
+    let mut process_machine = IMAGE_FILE_MACHINE_UNKNOWN;
+    let mut native_machine = IMAGE_FILE_MACHINE_UNKNOWN;
+
+    let success = unsafe {
+        IsWow64Process2(
+            GetCurrentProcess(),
+            &mut process_machine,
+            Some(&mut native_machine as *mut _),
+        )
+    };
+    if !success.as_bool() {
+        return None;
+    }
+
+    // not running under WOW64, which also covers x64 emulation on ARM64
+    let process_arch = match process_machine {
+        IMAGE_FILE_MACHINE_UNKNOWN => build_arch(),
+        machine => machine_name(machine),
+    };
+
+    Some((process_arch, machine_name(native_machine)))
+}
+
+/// Gets the native architecture of the machine, e.g. `x64` even for the x86 build.
+pub fn native_arch() -> Option<&'static str> {
+    lazy_static! {
+        static ref NATIVE_ARCH: Option<&'static str> =
+            get_machine_archs().map(|(_, native)| native);
+    }
+
+    *NATIVE_ARCH
+}
+
+fn build_arch() -> &'static str {
+    if cfg!(target_arch = "x86_64") {
+        "x64"
+    } else if cfg!(target_arch = "aarch64") {
+        "ARM64"
+    } else if cfg!(target_arch = "x86") {
+        "x86"
+    } else {
+        "unknown"
+    }
+}
+
+fn machine_name(machine: IMAGE_FILE_MACHINE) -> &'static str {
+    match machine {
+        IMAGE_FILE_MACHINE_AMD64 => "x64",
+        IMAGE_FILE_MACHINE_ARM64 => "ARM64",
+        IMAGE_FILE_MACHINE_I386 => "x86",
+        IMAGE_FILE_MACHINE_ARMNT => "ARM",
+        _ => "unknown",
+    }
+}
+
+pub fn process_is_elevated() -> bool {
+    unsafe {
+        let mut token: HANDLE = HANDLE::default();
+        let mut token_elevation_info: TOKEN_ELEVATION = Default::default();
+        let token_elevation_info_ptr = &mut token_elevation_info as *mut TOKEN_ELEVATION;
+        let mut size: u32 = 0;
+
+        let current_process = GetCurrentProcess();
+        if !OpenProcessToken(current_process, TOKEN_QUERY, &mut token).as_bool() {
+            return false;
+        }
+
+        let token = Handle::new(token);
+
+        let success = GetTokenInformation(
+            token.handle,
+            TokenElevation,
+            Some(token_elevation_info_ptr.cast()),
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut size,
+        )
+        .as_bool();
+
+        if !success {
+            return false;
+        }
+
+        token_elevation_info.TokenIsElevated != 0
+    }
+}
+
+/// Uninstalls an INF from the driver store, returning whether a reboot is required.
+pub fn uninstall_inf(inf_path: &Path) -> CResult<bool, WIN32_ERROR> {
+    unsafe {
+        let mut reboot: BOOL = false.into();
+        if !DiUninstallDriverW(None, &HSTRING::from(inf_path), 0, Some(&mut reboot)).as_bool() {
+            return Err(GetLastError());
+        }
+
+        Ok(reboot.as_bool())
+    }
+}
+
+/// A driver store folder whose INF is no longer published as any `oem*.inf`.
+#[derive(Debug)]
+pub struct OrphanedStoreFolder {
+    name: String,
+    inf_path: PathBuf,
+    provider: Option<String>,
+}
+
+impl OrphanedStoreFolder {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inf_path(&self) -> &Path {
+        &self.inf_path
+    }
+
+    pub fn provider(&self) -> Option<&str> {
+        self.provider.as_deref()
+    }
+}
+
+impl fmt::Display for OrphanedStoreFolder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Finds the `DriverStore\FileRepository` folders that no INF in `%WINDIR%\INF` points to,
+/// leaving out those of INFs that ship with Windows.
+pub fn enumerate_orphaned_store_folders() -> Result<Vec<OrphanedStoreFolder>, EnumerationError> {
+    let windir = std::env::var("WINDIR")
+        .into_report()
+        .change_context(EnumerationError::DriverStore)
+        .attach_printable("failed to get the Windows directory")?;
+    let inf_dir = Path::new(&windir).join("INF");
+    let repository = Path::new(&windir)
+        .join("System32")
+        .join("DriverStore")
+        .join("FileRepository");
+
+    let infs = inf_dir
+        .read_dir()
+        .into_report()
+        .change_context(EnumerationError::DriverStore)
+        .attach_printable_lazy(|| format!("failed to read {}", inf_dir.display()))?;
+
+    // inbox INFs are published too, not only the oem*.inf ones
+    let mut published = HashSet::new();
+    for inf in infs.flatten().map(|entry| entry.file_name()) {
+        let is_inf = Path::new(&inf)
+            .extension()
+            .map_or(false, |extension| extension.eq_ignore_ascii_case("inf"));
+        if !is_inf {
+            continue;
+        }
+
+        match get_inf_driver_store_location(&inf) {
+            Ok(location) => published.extend(location.as_deref().and_then(store_folder_name)),
+            Err(err) => warn!("{:?}", err),
+        }
+    }
+
+    let entries = repository
+        .read_dir()
+        .into_report()
+        .change_context(EnumerationError::DriverStore)
+        .attach_printable_lazy(|| format!("failed to read {}", repository.display()))?;
+
+    let mut folders = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !path.is_dir() || published.contains(&name.to_lowercase()) {
+            continue;
+        }
+
+        // folders are named after their INF, e.g. "wacom.inf_amd64_0123456789abcdef"
+        let inf_name = match name.split_once(".inf_") {
+            Some((stem, _)) => format!("{}.inf", stem),
+            None => continue,
+        };
+        let inf_path = path.join(inf_name);
+        if !inf_path.is_file() {
+            continue;
+        }
+
+        let provider = unsafe {
+            let inf_file = InfFileHandle {
+                handle: SetupOpenInfFileW(
+                    &HSTRING::from(inf_path.as_path()),
+                    None,
+                    INF_STYLE_WIN4.0 | INF_STYLE_OLDNT.0,
+                    None,
+                ),
+            };
+
+            match inf_file.handle.is_null() {
+                true => None,
+                false => get_inf_property(inf_file.handle, "Version", "Provider", parse_str)
+                    .ok()
+                    .flatten(),
+            }
+        };
+        if is_inbox_provider(provider.as_deref()) {
+            continue;
+        }
+
+        folders.push(OrphanedStoreFolder {
+            name,
+            inf_path,
+            provider,
+        });
+    }
+
+    Ok(folders)
+}
+
+/// Gets the lowercase name of the driver store folder an INF in the driver store is in.
+fn store_folder_name(inf_location: &str) -> Option<String> {
+    let folder = Path::new(inf_location).parent()?.file_name()?;
+    Some(folder.to_string_lossy().to_lowercase())
+}
+
+/// Whether an INF with this provider may ship with Windows. INFs whose provider cannot be
+/// read are assumed to, as they are not safe to remove.
+fn is_inbox_provider(provider: Option<&str>) -> bool {
+    provider.map_or(true, |provider| {
+        provider.trim().to_lowercase().starts_with("microsoft")
+    })
+}
+
+/// A file in `System32` or `SysWOW64`, along with what its version info says about it.
+#[derive(Serialize, Debug)]
+pub struct SystemFile {
+    path: String,
+    file_name: String,
+    company_name: Option<String>,
+    product_name: Option<String>,
+    file_version: Option<String>,
+}
+
+#[allow(dead_code)]
+impl SystemFile {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn company_name(&self) -> Option<&str> {
+        self.company_name.as_deref()
+    }
+
+    pub fn product_name(&self) -> Option<&str> {
+        self.product_name.as_deref()
+    }
+
+    pub fn file_version(&self) -> Option<&str> {
+        self.file_version.as_deref()
+    }
+}
+
+impl fmt::Display for SystemFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)
+    }
+}
+
+/// Enumerates the files in `System32` and `SysWOW64` whose name passes `filter`. Version info
+/// is only read for those, as the system directories hold thousands of files.
+pub fn enumerate_system_files(
+    filter: impl Fn(&str) -> bool,
+) -> Result<Vec<SystemFile>, EnumerationError> {
+    let windir = std::env::var("WINDIR").unwrap();
+    let windir = Path::new(&windir);
+    // 32-bit processes are redirected to SysWOW64 when accessing System32
+    let system32 = match windir.join("Sysnative").is_dir() {
+        true => windir.join("Sysnative"),
+        false => windir.join("System32"),
+    };
+
+    let mut files = Vec::new();
+    for dir in [system32, windir.join("SysWOW64")] {
+        if !dir.is_dir() {
+            continue;
+        }
+
+        let entries = dir
+            .read_dir()
+            .into_report()
+            .change_context(EnumerationError::SystemFile)
+            .attach_printable_lazy(|| format!("failed to read {}", dir.display()))?;
+
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            let path = entry.path();
+            if !filter(&file_name) || !path.is_file() {
+                continue;
+            }
+
+            let (company_name, product_name, file_version) = get_version_info(&path);
+            files.push(SystemFile {
+                path: path.to_string_lossy().to_string(),
+                file_name,
+                company_name,
+                product_name,
+                file_version,
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+type VersionInfo = (Option<String>, Option<String>, Option<String>);
+
+/// Reads the company name, product name and file version from the version resource of a file.
+fn get_version_info(path: &Path) -> VersionInfo {
+    unsafe {
+        let path = HSTRING::from(path);
+        let size = GetFileVersionInfoSizeW(&path, None);
+        if size == 0 {
+            return (None, None, None);
+        }
+
+        let mut data = vec![0u8; size as usize];
+        if !GetFileVersionInfoW(&path, 0, size, data.as_mut_ptr().cast()).as_bool() {
+            return (None, None, None);
+        }
+
+        let mut buffer: *mut c_void = std::ptr::null_mut();
+        let mut len: u32 = 0;
+        // strings are stored per language and code page, fall back to US English, Unicode
+        let translation = match VerQueryValueW(
+            data.as_ptr().cast(),
+            &HSTRING::from("\\VarFileInfo\\Translation"),
+            &mut buffer,
+            &mut len,
+        )
+        .as_bool()
+        {
+            true if len >= 4 => {
+                let [language, code_page] = (buffer as *const [u16; 2]).read_unaligned();
+                format!("{:04x}{:04x}", language, code_page)
+            }
+            _ => "040904b0".to_string(),
+        };
+
+        let query = |name: &str| -> Option<String> {
+            let mut buffer: *mut c_void = std::ptr::null_mut();
+            let mut len: u32 = 0;
+            let sub_block = format!("\\StringFileInfo\\{}\\{}", translation, name);
+            if !VerQueryValueW(
+                data.as_ptr().cast(),
+                &HSTRING::from(sub_block.as_str()),
+                &mut buffer,
+                &mut len,
+            )
+            .as_bool()
+                || len == 0
+            {
+                return None;
+            }
+
+            let value = std::slice::from_raw_parts(buffer as *const u16, len as usize);
+            let value = String::from_utf16_lossy(value);
+            let value = value.trim_end_matches('\0').trim();
+            (!value.is_empty()).then(|| value.to_string())
+        };
+
+        (
+            query("CompanyName"),
+            query("ProductName"),
+            query("FileVersion"),
+        )
+    }
+}
+
+/// Deletes a file, or schedules it to be deleted on the next reboot if it is in use.
+/// Returns whether a reboot is required.
+pub fn delete_file(path: &Path) -> CResult<bool, std::io::Error> {
+    match std::fs::remove_file(path) {
+        Ok(_) => Ok(false),
+        Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied => unsafe {
+            // loaded DLLs cannot be deleted, but they can be replaced on reboot
+            match MoveFileExW(
+                &HSTRING::from(path),
+                PCWSTR::null(),
+                MOVEFILE_DELAY_UNTIL_REBOOT,
+            )
+            .as_bool()
+            {
+                true => Ok(true),
+                false => Err(err),
+            }
+        },
+        Err(err) => Err(err),
     }
+}
 
-    pub fn key_name(&self) -> &str {
-        &self.key_name
-    }
+/// Relaunches the current executable with the `runas` verb, passing along the same arguments.
+pub fn relaunch_elevated() -> Result<(), ElevationError> {
+    let exe = std::env::current_exe()
+        .into_report()
+        .change_context(ElevationError {})
+        .attach_printable("failed to get current executable path")?;
+    // keep relative paths such as the log and dump directory where the user ran from
+    let current_dir = std::env::current_dir()
+        .into_report()
+        .change_context(ElevationError {})
+        .attach_printable("failed to get current directory")?;
+    let parameters = std::env::args()
+        .skip(1)
+        .map(|arg| quote_argument(&arg))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let instance = unsafe {
+        ShellExecuteW(
+            None,
+            &HSTRING::from("runas"),
+            &HSTRING::from(exe.as_path()),
+            &HSTRING::from(parameters.as_str()),
+            &HSTRING::from(current_dir.as_path()),
+            SW_SHOWNORMAL,
+        )
+    };
 
-    pub fn display_name(&self) -> Option<&str> {
-        self.display_name.as_deref()
+    // ShellExecuteW returns a value greater than 32 on success
+    if instance.0 <= 32 {
+        return Err(windows::core::Error::from_win32())
+            .into_report()
+            .change_context(ElevationError {})
+            .attach_printable_lazy(|| {
+                format!("failed to launch '{}' as administrator", exe.display())
+            });
     }
 
-    pub fn display_version(&self) -> Option<&str> {
-        self.display_version.as_deref()
+    Ok(())
+}
+
+/// Quotes an argument so that `CommandLineToArgvW` parses it back unchanged. Backslashes are
+/// only special right before a quote, including the closing one.
+fn quote_argument(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains([' ', '\t', '\n', '\x0B', '"']) {
+        return arg.to_string();
     }
 
-    pub fn publisher(&self) -> Option<&str> {
-        self.publisher.as_deref()
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
     }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
 
-    pub fn install_location(&self) -> Option<&str> {
-        self.install_location.as_deref()
+/// Expands `%VARIABLE%` references in `input`, returning it unchanged if expansion fails.
+pub fn expand_environment_strings(input: &str) -> String {
+    let source = HSTRING::from(input);
+    unsafe {
+        let size = ExpandEnvironmentStringsW(&source, None);
+        if size == 0 {
+            return input.to_string();
+        }
+
+        let mut buffer = vec![0u16; size as usize];
+        let len = ExpandEnvironmentStringsW(&source, Some(&mut buffer));
+        if len == 0 || len > size {
+            return input.to_string();
+        }
+
+        // the returned length includes the terminating null
+        String::from_utf16_lossy(&buffer[..len as usize - 1])
     }
+}
 
-    pub fn uninstall_string(&self) -> Option<&str> {
-        self.uninstall_string.as_deref()
+/// Formats a Win32 error code into a human-readable description, including the numeric code.
+pub fn describe_win32_error(error: WIN32_ERROR) -> String {
+    let mut buffer = [0u16; 512];
+    let len = unsafe {
+        FormatMessageW(
+            FORMAT_MESSAGE_FROM_SYSTEM | FORMAT_MESSAGE_IGNORE_INSERTS,
+            None,
+            error.0,
+            0,
+            PWSTR(buffer.as_mut_ptr()),
+            buffer.len() as u32,
+            None,
+        )
+    };
+
+    let message = String::from_utf16_lossy(&buffer[..len as usize]);
+    match message.trim() {
+        "" => format!("win32 error {} (0x{:08X})", error.0, error.0),
+        message => format!("win32 error {} (0x{:08X}): {}", error.0, error.0, message),
     }
 }
 
-impl fmt::Display for DriverPackage {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self.display_name() {
-            Some(display_name) => write!(f, "{}", display_name),
-            None => write!(f, "{}", self.key_name()),
+/// Whether the process has a visible top-level window, which for an uninstaller may be a
+/// prompt or only a progress window.
+pub fn has_visible_window(process_id: u32) -> bool {
+    struct Search {
+        process_id: u32,
+        found: bool,
+    }
+
+    unsafe extern "system" fn callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let search = &mut *(lparam.0 as *mut Search);
+        let mut window_process_id: u32 = 0;
+        GetWindowThreadProcessId(hwnd, Some(&mut window_process_id as *mut _));
+
+        if window_process_id == search.process_id && IsWindowVisible(hwnd).as_bool() {
+            search.found = true;
+            return false.into();
         }
+
+        true.into()
     }
-}
 
-pub fn process_is_elevated() -> bool {
+    let mut search = Search {
+        process_id,
+        found: false,
+    };
+
     unsafe {
-        let mut token: HANDLE = HANDLE::default();
-        let mut token_elevation_info: TOKEN_ELEVATION = Default::default();
-        let token_elevation_info_ptr = &mut token_elevation_info as *mut TOKEN_ELEVATION;
-        let mut size: u32 = 0;
+        EnumWindows(Some(callback), LPARAM(&mut search as *mut Search as isize));
+    }
 
-        let current_process = GetCurrentProcess();
-        if !OpenProcessToken(current_process, TOKEN_QUERY, &mut token).as_bool() {
-            return false;
-        }
+    search.found
+}
 
-        let token = Handle::new(token);
+/// The total CPU time the process has spent in kernel and user mode, in 100ns units.
+pub fn process_cpu_time(process_id: u32) -> Option<u64> {
+    fn to_u64(time: FILETIME) -> u64 {
+        (u64::from(time.dwHighDateTime) << 32) | u64::from(time.dwLowDateTime)
+    }
 
-        let success = GetTokenInformation(
-            token.handle,
-            TokenElevation,
-            Some(token_elevation_info_ptr.cast()),
-            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
-            &mut size,
-        )
-        .as_bool();
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id).ok()?;
+        let process = Handle::from(process);
 
-        if !success {
-            return false;
+        let mut creation_time = FILETIME::default();
+        let mut exit_time = FILETIME::default();
+        let mut kernel_time = FILETIME::default();
+        let mut user_time = FILETIME::default();
+        if !GetProcessTimes(
+            process.handle,
+            &mut creation_time,
+            &mut exit_time,
+            &mut kernel_time,
+            &mut user_time,
+        )
+        .as_bool()
+        {
+            return None;
         }
 
-        token_elevation_info.TokenIsElevated != 0
+        Some(to_u64(kernel_time) + to_u64(user_time))
     }
 }
 
@@ -444,18 +1477,19 @@ pub async fn wait_for_process_async(
         let process = Handle::from(process);
 
         loop {
-            let err = WaitForSingleObject(process.handle, 0);
+            if let Some(ct) = &ct {
+                if ct.is_cancelled() {
+                    bail!(WaitError::Cancelled);
+                }
+            }
+
+            // a short wait keeps the cancellation check above responsive
+            let wait_ms = constants::PROCESS_WAIT_INTERVAL.as_millis() as u32;
+            let err = WaitForSingleObject(process.handle, wait_ms);
             match err {
                 WAIT_OBJECT_0 => return Ok(()),
                 WAIT_ABANDONED => return Ok(()),
-                WAIT_TIMEOUT => {
-                    if let Some(ct) = &ct {
-                        if ct.is_cancelled() {
-                            bail!(WaitError::Timeout);
-                        }
-                    }
-                    tokio::time::sleep(Duration::from_millis(20)).await;
-                }
+                WAIT_TIMEOUT => tokio::time::sleep(constants::PROCESS_WAIT_INTERVAL).await,
                 WAIT_FAILED => {
                     return Err(windows::core::Error::from_win32())
                         .into_report()
@@ -467,14 +1501,42 @@ pub async fn wait_for_process_async(
     }
 }
 
+pub fn terminate_process(process_id: u32) -> Result<(), TerminateError> {
+    unsafe {
+        let process = OpenProcess(PROCESS_TERMINATE, false, process_id)
+            .into_report()
+            .attach_printable("failed to open process")
+            .change_context(TerminateError {})?;
+
+        let process = Handle::from(process);
+
+        if !TerminateProcess(process.handle, 1).as_bool() {
+            return Err(windows::core::Error::from_win32())
+                .into_report()
+                .change_context(TerminateError {});
+        }
+
+        Ok(())
+    }
+}
+
 pub fn enumerate_devices() -> Result<Vec<Device>, EnumerationError> {
+    enumerate_devices_with_flags(DIGCF_ALLCLASSES | DIGCF_PRESENT)
+}
+
+/// Enumerates devices including those that are not present, such as ones left behind by
+/// unplugged or partially uninstalled hardware.
+pub fn enumerate_all_devices() -> Result<Vec<Device>, EnumerationError> {
+    enumerate_devices_with_flags(DIGCF_ALLCLASSES)
+}
+
+fn enumerate_devices_with_flags(flags: u32) -> Result<Vec<Device>, EnumerationError> {
     unsafe {
         let mut devices = Vec::<Device>::new();
-        let device_info_set =
-            SetupDiGetClassDevsW(None, None, None, DIGCF_ALLCLASSES | DIGCF_PRESENT)
-                .into_report()
-                .change_context(EnumerationError::Device)
-                .attach_printable_lazy(|| "failed to initialize a device info set")?;
+        let device_info_set = SetupDiGetClassDevsW(None, None, None, flags)
+            .into_report()
+            .change_context(EnumerationError::Device)
+            .attach_printable_lazy(|| "failed to initialize a device info set")?;
         let mut device_info = SP_DEVINFO_DATA {
             cbSize: std::mem::size_of::<SP_DEVINFO_DATA>() as u32,
             ..Default::default()
@@ -495,7 +1557,7 @@ pub fn enumerate_devices() -> Result<Vec<Device>, EnumerationError> {
                 break;
             }
 
-            let device = create_device(device_info_set, device_info)?;
+            let device = create_device(device_info_set, device_info, flags & DIGCF_PRESENT != 0)?;
             devices.push(device);
         }
 
@@ -503,9 +1565,11 @@ pub fn enumerate_devices() -> Result<Vec<Device>, EnumerationError> {
     }
 }
 
+/// `known_present` is set when the device was enumerated with `DIGCF_PRESENT`.
 fn create_device(
     device_info_set: HDEVINFO,
     device_info: SP_DEVINFO_DATA,
+    known_present: bool,
 ) -> Result<Device, EnumerationError> {
     let generic = get_device_property(
         device_info_set,
@@ -516,15 +1580,40 @@ fn create_device(
     let generic = generic
         .change_context(EnumerationError::Device)
         .attach_printable("failed to get device 'DEVPKEY_Device_GenericDriverInstalled'")?
-        .unwrap();
+        .unwrap_or_default();
+    let present = get_device_property(
+        device_info_set,
+        &device_info,
+        &DEVPKEY_Device_IsPresent,
+        parse_bool,
+    );
+    let present = present
+        .change_context(EnumerationError::Device)
+        .attach_printable("failed to get device 'DEVPKEY_Device_IsPresent'")?;
+    // a missing property reads as false, so check for a live devnode before calling it absent
+    let present = known_present || present == Some(true) || has_devnode(device_info.DevInst);
     let instance_id = get_device_instance_id(device_info_set, &device_info)
         .change_context(EnumerationError::Device)
         .attach_printable("failed to get device InstanceID")?
-        .unwrap();
-    let hardware_ids =
-        get_device_registry_property(device_info_set, &device_info, SPDRP_HARDWAREID, parse_str)
-            .change_context(EnumerationError::Device)
-            .attach_printable("failed to get device registry property: 'SPDRP_HARDWAREID'")?;
+        .unwrap_or_default();
+    let hardware_ids = get_device_registry_property(
+        device_info_set,
+        &device_info,
+        SPDRP_HARDWAREID,
+        parse_multi_str,
+    )
+    .change_context(EnumerationError::Device)
+    .attach_printable("failed to get device registry property: 'SPDRP_HARDWAREID'")?
+    .unwrap_or_default();
+    let compatible_ids = get_device_registry_property(
+        device_info_set,
+        &device_info,
+        SPDRP_COMPATIBLEIDS,
+        parse_multi_str,
+    )
+    .change_context(EnumerationError::Device)
+    .attach_printable("failed to get device registry property: 'SPDRP_COMPATIBLEIDS'")?
+    .unwrap_or_default();
     let friendly_name =
         get_device_registry_property(device_info_set, &device_info, SPDRP_FRIENDLYNAME, parse_str)
             .change_context(EnumerationError::Device)
@@ -582,11 +1671,40 @@ fn create_device(
     )
     .change_context(EnumerationError::Device)
     .attach_printable("failed to get device 'DEVPKEY_Device_DriverProvider'")?;
+    let removal_policy = get_device_property(
+        device_info_set,
+        &device_info,
+        &DEVPKEY_Device_RemovalPolicy,
+        parse_u32,
+    )
+    .change_context(EnumerationError::Device)
+    .attach_printable("failed to get device 'DEVPKEY_Device_RemovalPolicy'")?
+    .and_then(RemovalPolicy::from_raw);
+    let parent = get_device_property(
+        device_info_set,
+        &device_info,
+        &DEVPKEY_Device_Parent,
+        parse_str,
+    )
+    .change_context(EnumerationError::Device)
+    .attach_printable("failed to get device 'DEVPKEY_Device_Parent'")?
+    .filter(|parent| !parent.is_empty());
+    let children = get_device_property(
+        device_info_set,
+        &device_info,
+        &DEVPKEY_Device_Children,
+        parse_multi_str,
+    )
+    .change_context(EnumerationError::Device)
+    .attach_printable("failed to get device 'DEVPKEY_Device_Children'")?
+    .unwrap_or_default();
 
     Ok(Device::new(
         generic,
+        present,
         instance_id,
         hardware_ids,
+        compatible_ids,
         friendly_name,
         description,
         manufacturer,
@@ -604,9 +1722,232 @@ fn create_device(
             .and_then(|f| f.parent())
             .and_then(|f| f.to_str())
             .map(|f| f.to_owned()),
+        removal_policy,
+        parent,
+        children,
     ))
 }
 
+/// Whether the device has a devnode, which only devices that are present have.
+fn has_devnode(dev_inst: u32) -> bool {
+    let mut status = 0;
+    let mut problem = 0;
+    unsafe { CM_Get_DevNode_Status(&mut status, &mut problem, dev_inst, 0) == CR_SUCCESS }
+}
+
+/// A property value read by [`inspect_device`].
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum PropertyValue {
+    String(String),
+    List(Vec<String>),
+    Bool(bool),
+    Number(u32),
+}
+
+#[derive(Clone, Copy)]
+enum PropertyKind {
+    String,
+    List,
+    Bool,
+    Number,
+}
+
+/// Properties dumped by [`inspect_device`] on top of the ones already kept in [`Device`].
+const INSPECTED_PROPERTIES: &[(&str, &DEVPROPKEY, PropertyKind)] = &[
+    (
+        "DEVPKEY_Device_Parent",
+        &DEVPKEY_Device_Parent,
+        PropertyKind::String,
+    ),
+    (
+        "DEVPKEY_Device_Children",
+        &DEVPKEY_Device_Children,
+        PropertyKind::List,
+    ),
+    (
+        "DEVPKEY_Device_Siblings",
+        &DEVPKEY_Device_Siblings,
+        PropertyKind::List,
+    ),
+    (
+        "DEVPKEY_Device_HardwareIds",
+        &DEVPKEY_Device_HardwareIds,
+        PropertyKind::List,
+    ),
+    (
+        "DEVPKEY_Device_CompatibleIds",
+        &DEVPKEY_Device_CompatibleIds,
+        PropertyKind::List,
+    ),
+    (
+        "DEVPKEY_Device_EnumeratorName",
+        &DEVPKEY_Device_EnumeratorName,
+        PropertyKind::String,
+    ),
+    (
+        "DEVPKEY_Device_BusReportedDeviceDesc",
+        &DEVPKEY_Device_BusReportedDeviceDesc,
+        PropertyKind::String,
+    ),
+    (
+        "DEVPKEY_Device_LocationInfo",
+        &DEVPKEY_Device_LocationInfo,
+        PropertyKind::String,
+    ),
+    (
+        "DEVPKEY_Device_LocationPaths",
+        &DEVPKEY_Device_LocationPaths,
+        PropertyKind::List,
+    ),
+    (
+        "DEVPKEY_Device_Service",
+        &DEVPKEY_Device_Service,
+        PropertyKind::String,
+    ),
+    (
+        "DEVPKEY_Device_UpperFilters",
+        &DEVPKEY_Device_UpperFilters,
+        PropertyKind::List,
+    ),
+    (
+        "DEVPKEY_Device_LowerFilters",
+        &DEVPKEY_Device_LowerFilters,
+        PropertyKind::List,
+    ),
+    (
+        "DEVPKEY_Device_DriverVersion",
+        &DEVPKEY_Device_DriverVersion,
+        PropertyKind::String,
+    ),
+    (
+        "DEVPKEY_Device_DriverInfPath",
+        &DEVPKEY_Device_DriverInfPath,
+        PropertyKind::String,
+    ),
+    (
+        "DEVPKEY_Device_DriverInfSection",
+        &DEVPKEY_Device_DriverInfSection,
+        PropertyKind::String,
+    ),
+    (
+        "DEVPKEY_Device_DriverProvider",
+        &DEVPKEY_Device_DriverProvider,
+        PropertyKind::String,
+    ),
+    (
+        "DEVPKEY_Device_GenericDriverInstalled",
+        &DEVPKEY_Device_GenericDriverInstalled,
+        PropertyKind::Bool,
+    ),
+    (
+        "DEVPKEY_Device_IsPresent",
+        &DEVPKEY_Device_IsPresent,
+        PropertyKind::Bool,
+    ),
+    (
+        "DEVPKEY_Device_RemovalPolicy",
+        &DEVPKEY_Device_RemovalPolicy,
+        PropertyKind::Number,
+    ),
+    (
+        "DEVPKEY_Device_ProblemCode",
+        &DEVPKEY_Device_ProblemCode,
+        PropertyKind::Number,
+    ),
+    (
+        "DEVPKEY_Device_DevNodeStatus",
+        &DEVPKEY_Device_DevNodeStatus,
+        PropertyKind::Number,
+    ),
+    (
+        "DEVPKEY_Device_ConfigFlags",
+        &DEVPKEY_Device_ConfigFlags,
+        PropertyKind::Number,
+    ),
+];
+
+/// A single device along with the raw values of every property in [`INSPECTED_PROPERTIES`].
+#[derive(Serialize)]
+pub struct DeviceInspection {
+    device: Device,
+    properties: BTreeMap<&'static str, Option<PropertyValue>>,
+}
+
+/// Opens only the device with the given instance id, present or not, and reads all of its
+/// properties that are of use when writing identifiers.
+pub fn inspect_device(instance_id: &str) -> Result<DeviceInspection, EnumerationError> {
+    unsafe {
+        let device_info_set = SetupDiCreateDeviceInfoList(None, None)
+            .into_report()
+            .change_context(EnumerationError::Device)
+            .attach_printable_lazy(|| "failed to initialize a device info set")?;
+        let mut device_info = SP_DEVINFO_DATA {
+            cbSize: std::mem::size_of::<SP_DEVINFO_DATA>() as u32,
+            ..Default::default()
+        };
+
+        let result = match SetupDiOpenDeviceInfoW(
+            device_info_set,
+            &HSTRING::from(instance_id),
+            None,
+            0,
+            Some(&mut device_info as *mut _),
+        )
+        .as_bool()
+        {
+            true => inspect_device_info(device_info_set, device_info),
+            false => Err(windows::core::Error::from_win32())
+                .into_report()
+                .change_context(EnumerationError::Device)
+                .attach_printable_lazy(|| format!("failed to open device '{instance_id}'")),
+        };
+
+        SetupDiDestroyDeviceInfoList(device_info_set);
+        result
+    }
+}
+
+fn inspect_device_info(
+    device_info_set: HDEVINFO,
+    device_info: SP_DEVINFO_DATA,
+) -> Result<DeviceInspection, EnumerationError> {
+    let mut properties = BTreeMap::new();
+
+    for &(name, prop_key, kind) in INSPECTED_PROPERTIES {
+        let value = match kind {
+            PropertyKind::String => {
+                get_device_property(device_info_set, &device_info, prop_key, parse_str)
+                    .map(|value| value.map(PropertyValue::String))
+            }
+            PropertyKind::List => {
+                get_device_property(device_info_set, &device_info, prop_key, parse_multi_str)
+                    .map(|value| value.map(PropertyValue::List))
+            }
+            PropertyKind::Bool => {
+                get_device_property(device_info_set, &device_info, prop_key, parse_bool)
+                    .map(|value| value.map(PropertyValue::Bool))
+            }
+            PropertyKind::Number => {
+                get_device_property(device_info_set, &device_info, prop_key, parse_u32)
+                    .map(|value| value.map(PropertyValue::Number))
+            }
+        };
+        let value = value
+            .change_context(EnumerationError::Device)
+            .attach_printable_lazy(|| format!("failed to get device '{name}'"))?;
+
+        properties.insert(name, value);
+    }
+
+    Ok(DeviceInspection {
+        device: create_device(device_info_set, device_info, false)?,
+        properties,
+    })
+}
+
+/// Enumerates every third-party (`oem*.inf`) driver in the driver store regardless of its class,
+/// so extension and SoftwareComponent INFs are included alongside function drivers.
 pub fn enumerate_drivers() -> Result<Vec<Driver>, EnumerationError> {
     unsafe {
         let mut drivers = Vec::<Driver>::new();
@@ -644,6 +1985,20 @@ fn create_driver(inf: OsString, inf_file: InfFileHandle) -> Result<Driver, Enume
         get_inf_driver_store_location(&inf).change_context(EnumerationError::Driver)?;
     let inf_provider = get_inf_property(inf_file.handle, "Version", "Provider", parse_str)
         .change_context(EnumerationError::Driver)?;
+    // DriverVer is "mm/dd/yyyy[,x.y.z.w]"
+    let driver_ver = get_inf_property(inf_file.handle, "Version", "DriverVer", parse_str)
+        .ok()
+        .flatten();
+    let (driver_date, driver_version) = match &driver_ver {
+        Some(driver_ver) => match driver_ver.split_once(',') {
+            Some((date, version)) => (
+                Some(date.trim().to_string()),
+                Some(version.trim().to_string()),
+            ),
+            None => (Some(driver_ver.trim().to_string()), None),
+        },
+        None => (None, None),
+    };
     let class_name = get_inf_property(inf_file.handle, "Version", "Class", parse_str)
         .change_context(EnumerationError::Driver)?;
     let class_uuid = get_inf_property(inf_file.handle, "Version", "ClassGUID", parse_uuid)
@@ -663,53 +2018,182 @@ fn create_driver(inf: OsString, inf_file: InfFileHandle) -> Result<Driver, Enume
             .and_then(|f| f.to_str())
             .map(|f| f.to_owned()),
         inf_provider,
+        driver_version,
+        driver_date,
         class_name,
         class_uuid,
     ))
 }
 
+/// Parses a `DriverVer` date. `mm/dd/yyyy` is expected, but dashes or dots as separators,
+/// missing leading zeros and year-first dates are accepted too.
+fn parse_driver_date(date: &str) -> Option<NaiveDate> {
+    let parts: Vec<u32> = date
+        .split(|c| c == '/' || c == '-' || c == '.')
+        .map(|part| part.trim().parse().ok())
+        .collect::<Option<_>>()?;
+
+    match parts[..] {
+        [year, month, day] if year > 31 => NaiveDate::from_ymd_opt(year as i32, month, day),
+        [month, day, year] => NaiveDate::from_ymd_opt(year as i32, month, day),
+        _ => None,
+    }
+}
+
 pub fn enumerate_driver_packages() -> Result<Vec<DriverPackage>, EnumerationError> {
     let mut driver_packages = Vec::<DriverPackage>::new();
 
     let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
     let uninstall_path = Path::new(X64_UNINSTALL_KEY);
     let x86_uninstall_path = Path::new(X86_UNINSTALL_KEY);
 
     let uninstall_key = open_key(&hklm, uninstall_path);
     let x86_uninstall_key = open_key(&hklm, x86_uninstall_path);
+    let user_uninstall_key = open_key(&hkcu, uninstall_path);
 
     match uninstall_key {
-        Ok(key) => {
-            key.enum_keys().map(|x| x.unwrap()).for_each(|subkey_name| {
-                if let Ok(subkey) = key.open_subkey(&subkey_name) {
-                    let subkey_path: String = Path::join(uninstall_path, subkey_name)
-                        .to_str()
-                        .unwrap()
-                        .to_string();
-                    driver_packages.push(DriverPackage::from(&subkey, subkey_path, false));
-                }
-            });
-        }
+        Ok(key) => push_driver_packages(
+            &mut driver_packages,
+            &key,
+            uninstall_path,
+            false,
+            PackageScope::Machine,
+        ),
         Err(error) => return Err(error).attach_printable("failed to open uninstall key"),
     }
 
     if let Ok(key) = x86_uninstall_key {
-        key.enum_keys().map(|x| x.unwrap()).for_each(|subkey_name| {
-            if let Ok(subkey) = key.open_subkey(&subkey_name) {
-                let subkey_path: String = Path::join(x86_uninstall_path, subkey_name)
-                    .to_str()
-                    .unwrap()
-                    .to_string();
-                driver_packages.push(DriverPackage::from(&subkey, subkey_path, true));
-            }
-        });
+        push_driver_packages(
+            &mut driver_packages,
+            &key,
+            x86_uninstall_path,
+            true,
+            PackageScope::Machine,
+        );
+    }
+
+    if let Ok(key) = user_uninstall_key {
+        push_driver_packages(
+            &mut driver_packages,
+            &key,
+            uninstall_path,
+            false,
+            PackageScope::User,
+        );
     }
 
     Ok(driver_packages)
 }
 
-fn open_key(hklm: &RegKey, uninstall_path: &Path) -> Result<RegKey, EnumerationError> {
-    hklm.open_subkey(uninstall_path)
+pub fn enumerate_class_filters() -> Result<Vec<ClassFilter>, EnumerationError> {
+    let class_key = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey(CLASS_KEY)
+        .into_report()
+        .attach_printable_lazy(|| format!("failed to open registry key '{}'", CLASS_KEY))
+        .change_context(EnumerationError::ClassFilter)?;
+    let mut filters = Vec::<ClassFilter>::new();
+
+    for subkey_name in class_key.enum_keys().flatten() {
+        let Ok(class_guid) = Uuid::parse_str(&subkey_name) else {
+            continue;
+        };
+        let Ok(subkey) = class_key.open_subkey(&subkey_name) else {
+            continue;
+        };
+        let class_name: Option<String> = subkey.get_value("Class").ok();
+
+        for position in [FilterPosition::UpperFilters, FilterPosition::LowerFilters] {
+            let names: Vec<String> = subkey.get_value(position.value_name()).unwrap_or_default();
+            filters.extend(
+                names
+                    .into_iter()
+                    .filter(|name| !name.is_empty())
+                    .map(|name| ClassFilter {
+                        name,
+                        position,
+                        class_name: class_name.clone(),
+                        class_guid,
+                    }),
+            );
+        }
+    }
+
+    Ok(filters)
+}
+
+/// Enumerates services of the kernel driver type from the service table.
+pub fn enumerate_kernel_drivers() -> Result<Vec<KernelDriver>, EnumerationError> {
+    let services_key = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey(SERVICES_KEY)
+        .into_report()
+        .attach_printable_lazy(|| format!("failed to open registry key '{}'", SERVICES_KEY))
+        .change_context(EnumerationError::KernelDriver)?;
+
+    let kernel_drivers = services_key
+        .enum_keys()
+        .flatten()
+        .filter_map(|name| {
+            let service = services_key.open_subkey(&name).ok()?;
+            let service_type: u32 = service.get_value("Type").ok()?;
+            if service_type != SERVICE_KERNEL_DRIVER {
+                return None;
+            }
+
+            Some(KernelDriver {
+                display_name: service.get_value("DisplayName").ok(),
+                image_path: service.get_value("ImagePath").ok(),
+                start_type: service
+                    .get_value::<u32, _>("Start")
+                    .ok()
+                    .and_then(StartType::from_value),
+                name,
+            })
+        })
+        .collect();
+
+    Ok(kernel_drivers)
+}
+
+/// Removes a filter from its class' filter list, leaving the other filters in place.
+pub fn remove_class_filter(filter: &ClassFilter) -> std::io::Result<()> {
+    let key_path = format!("{}\\{{{}}}", CLASS_KEY, filter.class_guid);
+    let key = RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey_with_flags(key_path, KEY_READ | KEY_WRITE)?;
+    let value_name = filter.position.value_name();
+
+    let remaining: Vec<String> = key
+        .get_value::<Vec<String>, _>(value_name)?
+        .into_iter()
+        .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case(&filter.name))
+        .collect();
+
+    match remaining.is_empty() {
+        true => key.delete_value(value_name),
+        false => key.set_value(value_name, &remaining),
+    }
+}
+
+fn push_driver_packages(
+    driver_packages: &mut Vec<DriverPackage>,
+    key: &RegKey,
+    key_path: &Path,
+    x86: bool,
+    scope: PackageScope,
+) {
+    key.enum_keys().map(|x| x.unwrap()).for_each(|subkey_name| {
+        if let Ok(subkey) = key.open_subkey(&subkey_name) {
+            let subkey_path: String = Path::join(key_path, subkey_name)
+                .to_str()
+                .unwrap()
+                .to_string();
+            driver_packages.push(DriverPackage::from(&subkey, subkey_path, x86, scope));
+        }
+    });
+}
+
+fn open_key(root: &RegKey, uninstall_path: &Path) -> Result<RegKey, EnumerationError> {
+    root.open_subkey(uninstall_path)
         .into_report()
         .attach_printable_lazy(|| {
             format!(
@@ -759,6 +2243,7 @@ fn get_inf_driver_store_location(inf_name: &OsStr) -> Result<Option<String>, Ffi
         },
         parse_str,
         &[ERROR_NOT_FOUND, ERROR_FILE_NOT_FOUND],
+        || format!("driver store location of {}", inf_name.to_string_lossy()),
     )
     .attach_printable_lazy(|| {
         format!(
@@ -803,6 +2288,7 @@ where
         },
         parser,
         &[],
+        || format!("inf property '{}' in section '{}'", key, section),
     )
     .attach_printable_lazy(|| {
         format!(
@@ -842,6 +2328,7 @@ fn get_device_instance_id(
         },
         parse_str,
         &[],
+        || "device instance id".to_string(),
     )
 }
 
@@ -874,6 +2361,7 @@ fn get_device_registry_property<T: Default>(
         },
         parser,
         &[ERROR_INVALID_DATA],
+        || format!("device registry property {}", prop),
     )
 }
 
@@ -908,18 +2396,37 @@ fn get_device_property<T: Default>(
         },
         parser,
         &[ERROR_NOT_FOUND],
+        || format!("device property {:?}[{}]", prop_key.fmtid, prop_key.pid),
     )
 }
 
 fn parse_str(buffer: &[u8]) -> Result<String, FfiError> {
+    // only the terminating nulls are dropped, so a string with embedded nulls is kept whole
+    // instead of being cut at the first one
     let slice = to_u16_slice(buffer);
-    Ok(HSTRING::from_wide(&slice[0..slice.len() - 1])
+    let end = slice.iter().rposition(|&c| c != 0).map_or(0, |i| i + 1);
+    Ok(HSTRING::from_wide(&slice[..end])
         .into_report()
         .change_context(FfiError::Parser)
         .attach_printable("failed to parse string")?
         .to_string())
 }
 
+/// Parses a `REG_MULTI_SZ` style list of null-terminated strings.
+fn parse_multi_str(buffer: &[u8]) -> Result<Vec<String>, FfiError> {
+    to_u16_slice(buffer)
+        .split(|&c| c == 0)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            HSTRING::from_wide(s)
+                .into_report()
+                .change_context(FfiError::Parser)
+                .attach_printable("failed to parse string list")
+                .map(|s| s.to_string())
+        })
+        .collect()
+}
+
 fn parse_uuid(buffer: &[u8]) -> Result<Uuid, FfiError> {
     let string = parse_str(buffer)?;
     let str = string.trim_matches(|c: char| !c.is_ascii_alphanumeric());
@@ -938,27 +2445,60 @@ fn parse_bool(buffer: &[u8]) -> Result<bool, FfiError> {
     Ok((buffer[0] as i8) == -1)
 }
 
+fn parse_u32(buffer: &[u8]) -> Result<u32, FfiError> {
+    let bytes: [u8; 4] = buffer
+        .get(..4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| report!(FfiError::Parser))
+        .attach_printable_lazy(|| format!("expected 4 bytes, got {}", buffer.len()))?;
+
+    Ok(u32::from_le_bytes(bytes))
+}
+
+static TRACE_FFI: AtomicBool = AtomicBool::new(false);
+
+/// Enables logging the raw results of every property lookup at debug level.
+pub fn set_ffi_tracing(enabled: bool) {
+    TRACE_FFI.store(enabled, Ordering::Relaxed);
+}
+
+fn trace_ffi(describe: impl FnOnce() -> String, message: impl FnOnce() -> String) {
+    if TRACE_FFI.load(Ordering::Relaxed) {
+        debug!("{}: {}", describe(), message());
+    }
+}
+
 fn generic_get<T>(
     getter: impl Fn(Option<&mut [u8]>) -> CResult<(), GenericGetError>,
     parser: impl FnOnce(&[u8]) -> Result<T, FfiError>,
     skip_codes: &[WIN32_ERROR],
+    describe: impl Fn() -> String,
 ) -> Result<Option<T>, FfiError>
 where
     T: Default,
 {
-    let size: u32;
-    let get = getter(None);
-    match get {
-        CResult::Ok(_) => panic!("should not be able to get value without buffer"),
+    let size = match getter(None) {
+        // nothing to retrieve if the getter succeeds without a buffer
+        CResult::Ok(_) => {
+            trace_ffi(&describe, || "succeeded without a buffer".to_string());
+            return Ok(None);
+        }
         CResult::Err(GenericGetError {
             required_size,
             error,
         }) => match error {
-            _ if skip_codes.contains(&error) => return Ok(Some(T::default())),
+            _ if skip_codes.contains(&error) => {
+                trace_ffi(&describe, || format!("skipped on {:?}", error));
+                return Ok(Some(T::default()));
+            }
             ERROR_INSUFFICIENT_BUFFER | ERROR_INVALID_USER_BUFFER | NO_ERROR => {
-                size = required_size
+                trace_ffi(&describe, || {
+                    format!("requires {} bytes ({:?})", required_size, error)
+                });
+                required_size
             }
             _ => {
+                trace_ffi(&describe, || format!("size query failed with {:?}", error));
                 let error: windows::core::Error = error.into();
                 return Err(error)
                     .into_report()
@@ -966,16 +2506,19 @@ where
                     .change_context(FfiError::Io);
             }
         },
+    };
+
+    if size == 0 {
+        return Ok(None);
     }
 
-    let mut buffer: Vec<u8> = Vec::with_capacity(size as usize);
-    let buffer_slice =
-        unsafe { std::slice::from_raw_parts_mut(buffer.as_mut_ptr(), buffer.capacity()) };
+    let mut buffer = vec![0u8; size as usize];
 
-    let get = getter(Some(buffer_slice));
+    let get = getter(Some(buffer.as_mut_slice()));
     match get {
-        CResult::Ok(_) => Ok(Some(parser(buffer_slice)?)),
+        CResult::Ok(_) => Ok(Some(parser(&buffer)?)),
         CResult::Err(GenericGetError { error, .. }) => {
+            trace_ffi(&describe, || format!("failed with {:?}", error));
             let error: windows::core::Error = error.into();
             Err(error)
                 .into_report()
@@ -1002,3 +2545,98 @@ fn to_u16_slice_mut(slice: &mut [u8]) -> &mut [u16] {
     );
     unsafe { std::slice::from_raw_parts_mut(slice.as_mut_ptr() as *mut u16, slice.len() / 2) }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide_bytes(wide: &[u16]) -> Vec<u8> {
+        wide.iter().flat_map(|c| c.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn parse_str_of_empty_value_is_empty() {
+        assert_eq!(parse_str(&wide_bytes(&[0])).unwrap(), "");
+    }
+
+    #[test]
+    fn parse_str_drops_only_terminating_nulls() {
+        let buffer = wide_bytes(&[b'a' as u16, 0, b'b' as u16, 0, 0]);
+        assert_eq!(parse_str(&buffer).unwrap(), "a\0b");
+    }
+
+    #[test]
+    fn generic_get_of_zero_size_value_is_none() {
+        let value = generic_get(
+            |buffer| {
+                assert!(
+                    buffer.is_none(),
+                    "nothing should be read from an empty value"
+                );
+                Err(GenericGetError {
+                    required_size: 0,
+                    error: ERROR_INSUFFICIENT_BUFFER,
+                })
+            },
+            parse_str,
+            &[],
+            || "empty value".to_string(),
+        );
+        assert_eq!(value.unwrap(), None);
+    }
+
+    #[test]
+    fn generic_get_succeeding_without_buffer_is_none() {
+        let value = generic_get(|_| Ok(()), parse_str, &[], || "no value".to_string());
+        assert_eq!(value.unwrap(), None);
+    }
+
+    #[test]
+    fn generic_get_reads_into_sized_buffer() {
+        let expected = wide_bytes(&[b'a' as u16, 0]);
+        let value = generic_get(
+            |buffer| match buffer {
+                Some(buffer) => {
+                    buffer.copy_from_slice(&expected);
+                    Ok(())
+                }
+                None => Err(GenericGetError {
+                    required_size: expected.len() as u32,
+                    error: ERROR_INSUFFICIENT_BUFFER,
+                }),
+            },
+            parse_str,
+            &[],
+            || "value".to_string(),
+        );
+        assert_eq!(value.unwrap().as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn arguments_are_quoted_for_command_line_to_argv() {
+        assert_eq!(quote_argument("--dry-run"), "--dry-run");
+        assert_eq!(quote_argument(r"C:\dumps\"), r"C:\dumps\");
+        assert_eq!(quote_argument(""), r#""""#);
+        assert_eq!(quote_argument(r"C:\my dumps\"), r#""C:\my dumps\\""#);
+        assert_eq!(quote_argument(r#"a "b""#), r#""a \"b\"""#);
+        assert_eq!(quote_argument(r#"a\"b"#), r#""a\\\"b""#);
+    }
+
+    #[test]
+    fn inbox_store_folders_are_published_by_name() {
+        let location =
+            r"C:\Windows\System32\DriverStore\FileRepository\WinUSB.inf_amd64_0123\winusb.inf";
+        assert_eq!(
+            store_folder_name(location).as_deref(),
+            Some("winusb.inf_amd64_0123")
+        );
+    }
+
+    #[test]
+    fn inbox_providers_are_never_orphaned() {
+        assert!(is_inbox_provider(Some("Microsoft")));
+        assert!(is_inbox_provider(Some("Microsoft Corporation")));
+        assert!(is_inbox_provider(None));
+        assert!(!is_inbox_provider(Some("Wacom Technology, Corp.")));
+    }
+}